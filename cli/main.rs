@@ -1,16 +1,324 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::{App, Arg};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{sync::Arc, time::Duration};
+use yuyu_core::{
+    cookies::CookieSource,
+    dedup::{self, Store},
+    history, Config, Format, History, Progress, Registry,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let matches = App::new("yuyu")
         .version("1.0")
-        .arg(Arg::with_name("url").required(true))
+        .arg(Arg::with_name("url").required_unless("overlaps"))
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("The config file to use [default: ~/.config/yuyu/config.toml]"),
+        )
+        .arg(
+            Arg::with_name("user-agent")
+                .long("user-agent")
+                .takes_value(true)
+                .help("User-Agent sent with every request"),
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .long("proxy")
+                .takes_value(true)
+                .help("HTTP or SOCKS5 proxy, e.g. socks5://127.0.0.1:9050"),
+        )
+        .arg(
+            Arg::with_name("connect-timeout")
+                .long("connect-timeout")
+                .takes_value(true)
+                .help("Seconds to wait for a connection"),
+        )
+        .arg(
+            Arg::with_name("read-timeout")
+                .long("read-timeout")
+                .takes_value(true)
+                .help("Seconds to wait for a response or for more of it"),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .short("H")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Extra header sent with every request, e.g. \"Accept-Language: en\""),
+        )
+        .arg(
+            Arg::with_name("concurrency")
+                .long("concurrency")
+                .short("j")
+                .takes_value(true)
+                .help("Maximum number of pages downloaded at the same time"),
+        )
+        .arg(
+            Arg::with_name("per-host")
+                .long("per-host")
+                .takes_value(true)
+                .help("Maximum number of pages downloaded at the same time from a single host"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .help("Directory everything is downloaded into"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["folder", "cbz", "epub", "pdf"])
+                .help("How comics are stored"),
+        )
+        .arg(
+            Arg::with_name("original")
+                .long("original")
+                .help("Download original images while the image limit of the account allows it (e-hentai, exhentai)"),
+        )
+        .arg(
+            Arg::with_name("no-comic-info")
+                .long("no-comic-info")
+                .help("Don't save a ComicInfo.xml with the metadata of comics"),
+        )
+        .arg(
+            Arg::with_name("no-metadata")
+                .long("no-metadata")
+                .help("Don't save a metadata.json next to each download"),
+        )
+        .arg(
+            Arg::with_name("template")
+                .long("template")
+                .takes_value(true)
+                .help("Path of each page inside the output directory, e.g. \"{authors}/{title}/{page:03}.{ext}\""),
+        )
+        .arg(
+            Arg::with_name("debug-dump")
+                .long("debug-dump")
+                .takes_value(true)
+                .help("Directory where the raw responses of the sites are saved"),
+        )
+        .arg(
+            Arg::with_name("cookies")
+                .long("cookies")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Import cookies from a cookies.txt in the Netscape format"),
+        )
+        .arg(
+            Arg::with_name("cookies-from-browser")
+                .long("cookies-from-browser")
+                .takes_value(true)
+                .value_name("BROWSER[:PROFILE]")
+                .help("Import cookies from firefox or chromium"),
+        )
+        .arg(
+            Arg::with_name("history")
+                .long("history")
+                .takes_value(true)
+                .help("The file keeping track of the comics downloaded [default: OUTPUT/history.sqlite3]"),
+        )
+        .arg(
+            Arg::with_name("no-history")
+                .long("no-history")
+                .conflicts_with("history")
+                .help("Don't check or record the comics downloaded"),
+        )
+        .arg(
+            Arg::with_name("force")
+                .short("f")
+                .long("force")
+                .help("Download comics again even if they already were"),
+        )
+        .arg(
+            Arg::with_name("dedup")
+                .long("dedup")
+                .help("Hardlink pages already downloaded as part of other comics"),
+        )
+        .arg(
+            Arg::with_name("perceptual-hash")
+                .long("perceptual-hash")
+                .requires("dedup")
+                .help("Also match pages saved again with a different quality or format"),
+        )
+        .arg(
+            Arg::with_name("overlaps")
+                .long("overlaps")
+                .takes_value(true)
+                .value_name("RATIO")
+                .help("List the comics sharing at least RATIO of their pages, from 0 to 1, and exit"),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .takes_value(true)
+                .help("Maximum number of times a failed request is retried"),
+        )
         .get_matches();
 
+    let config = match matches.value_of("config") {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+    for key in config.ignored.iter() {
+        eprintln!("Unknown config key: {}", key);
+    }
+
+    // Hidden until there is something to download
+    let bar = ProgressBar::hidden();
+    bar.set_style(ProgressStyle::default_bar().template("{msg} [{bar:40}] {pos}/{len}"));
+
+    let reporter = bar.clone();
+    let mut session = config
+        .session_builder()
+        .reporter(move |progress| match progress {
+            Progress::Comic { title, pages } => {
+                reporter.set_message(&title);
+                reporter.set_length(pages as u64);
+                reporter.set_draw_target(ProgressDrawTarget::stderr());
+            }
+            Progress::Skipped { title, path } => {
+                eprintln!(
+                    "{} was already downloaded to {}, use --force to download it again",
+                    title,
+                    path.display()
+                );
+            }
+            Progress::Page { .. } => reporter.inc(1),
+            Progress::Retry {
+                url,
+                attempt,
+                delay,
+                error,
+            } => {
+                let message = format!(
+                    "{}, retrying {} in {:.1}s (attempt {})",
+                    error,
+                    url,
+                    delay.as_secs_f32(),
+                    attempt
+                );
+
+                if reporter.length() == 0 {
+                    eprintln!("{}", message);
+                } else {
+                    reporter.println(message);
+                }
+            }
+        });
+
+    if let Some(user_agent) = matches.value_of("user-agent") {
+        session = session.user_agent(user_agent);
+    }
+    if let Some(proxy) = matches.value_of("proxy") {
+        session = session.proxy(proxy);
+    }
+    if let Some(timeout) = matches.value_of("connect-timeout") {
+        session = session.connect_timeout(Duration::from_secs(timeout.parse()?));
+    }
+    if let Some(timeout) = matches.value_of("read-timeout") {
+        session = session.read_timeout(Duration::from_secs(timeout.parse()?));
+    }
+    if let Some(path) = matches.value_of("cookies") {
+        session = session.import_cookies(CookieSource::Netscape(path.into()));
+    }
+    if let Some(source) = matches.value_of("cookies-from-browser") {
+        session = session.import_cookies(source.parse()?);
+    }
+    if let Some(directory) = matches.value_of("debug-dump") {
+        session = session.debug_dump(directory);
+    }
+    if let Some(retries) = matches.value_of("retries") {
+        session = session.retry(config.retry_policy(Some(retries.parse()?)));
+    }
+    for header in matches.values_of("header").into_iter().flatten() {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid header: {}", header))?;
+        session = session.header(name.trim(), value.trim());
+    }
+
+    let session = session.build()?;
+
+    let mut options = config.download_options();
+
+    if let Some(concurrency) = matches.value_of("concurrency") {
+        options.concurrency = concurrency.parse()?;
+    }
+    if let Some(per_host) = matches.value_of("per-host") {
+        options.per_host = per_host.parse()?;
+    }
+    if let Some(output) = matches.value_of("output") {
+        options.output = output.into();
+    }
+    match matches.value_of("format") {
+        Some("cbz") => options.format = Format::Cbz,
+        Some("epub") => options.format = Format::Epub,
+        Some("pdf") => options.format = Format::Pdf,
+        Some(_) => options.format = Format::Folder,
+        None => (),
+    }
+    if matches.is_present("no-comic-info") {
+        options.comic_info = false;
+    }
+    if matches.is_present("no-metadata") {
+        options.metadata = false;
+    }
+    if let Some(template) = matches.value_of("template") {
+        options.template = template.to_string();
+    }
+    if let Some(ratio) = matches.value_of("overlaps") {
+        let store = Store::open(options.output.join(dedup::DIRECTORY_NAME), false)?;
+        for overlap in store.overlaps(ratio.parse()?)? {
+            println!(
+                "{} {} and {} {} share {} pages ({:.0}%)",
+                overlap.first.0,
+                overlap.first.1,
+                overlap.second.0,
+                overlap.second.1,
+                overlap.shared,
+                overlap.ratio * 100.0
+            );
+        }
+
+        return Ok(());
+    }
+
+    if !matches.is_present("no-history") {
+        let path = match matches.value_of("history") {
+            Some(path) => path.into(),
+            None => options.output.join(history::FILE_NAME),
+        };
+        options.history = Some(Arc::new(History::open(path)?));
+    }
+    options.force = matches.is_present("force");
+    if matches.is_present("dedup") {
+        let store = Store::open(
+            options.output.join(dedup::DIRECTORY_NAME),
+            matches.is_present("perceptual-hash"),
+        )?;
+        options.dedup = Some(Arc::new(store));
+    }
+
+    let mut extractor_options = config.extractor_options();
+    if matches.is_present("original") {
+        extractor_options.ehentai.original = true;
+        extractor_options.exhentai.original = true;
+    }
+    let registry = Registry::with_options(&extractor_options);
+
     let url = matches.value_of("url").unwrap();
+    yuyu_core::start(&session, &registry, url, &options).await?;
 
-    yuyu_core::start(url).await?;
+    bar.finish();
 
     Ok(())
 }
@@ -0,0 +1,213 @@
+use chrono::{Datelike, NaiveDateTime};
+use reqwest::Url;
+use std::fmt::Write;
+
+use crate::comic::{Comic, Page};
+
+/// The name readers look for inside archives and next to folders.
+pub const FILE_NAME: &str = "ComicInfo.xml";
+
+/// Generates the ComicInfo.xml of a comic, following the ComicRack schema
+/// that media servers like Komga and Kavita read.
+///
+/// Elements are written in the order of the schema since some readers validate it.
+/// `url` is the gallery the comic was downloaded from.
+/// If `with_cover` is true the cover is listed as the first image, before the pages.
+pub fn comic_info(comic: &Comic, url: &Url, with_cover: bool) -> String {
+    let mut xml = String::new();
+
+    xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<ComicInfo xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n");
+
+    element(&mut xml, "Title", &comic.title);
+    element(&mut xml, "Series", &comic.title);
+    element(&mut xml, "Notes", &format!("{} {}", comic.site, comic.id));
+
+    if let Some(date) = NaiveDateTime::from_timestamp_opt(comic.upload_date as i64, 0) {
+        element(&mut xml, "Year", &date.year().to_string());
+        element(&mut xml, "Month", &date.month().to_string());
+        element(&mut xml, "Day", &date.day().to_string());
+    }
+
+    element(&mut xml, "Writer", &comic.authors.join(", "));
+    element(&mut xml, "Genre", &comic.categories.join(", "));
+    let tags: Vec<String> = comic.tags.iter().map(ToString::to_string).collect();
+    element(&mut xml, "Tags", &tags.join(", "));
+    element(&mut xml, "Web", url.as_str());
+
+    let pages: Vec<(&Page, &str)> = if with_cover {
+        std::iter::once((&comic.cover, "FrontCover"))
+            .chain(comic.pages.iter().map(|page| (page, "Story")))
+            .collect()
+    } else {
+        comic.pages.iter().map(|page| (page, "Story")).collect()
+    };
+
+    element(&mut xml, "PageCount", &pages.len().to_string());
+
+    if let Some(code) = comic.languages.first().and_then(|l| language_code(l)) {
+        element(&mut xml, "LanguageISO", code);
+    }
+
+    element(&mut xml, "Characters", &comic.characters.join(", "));
+    element(&mut xml, "Teams", &comic.groups.join(", "));
+
+    xml.push_str("  <Pages>\n");
+    for (index, (page, page_type)) in pages.iter().enumerate() {
+        write!(xml, "    <Page Image=\"{}\" Type=\"{}\"", index, page_type).unwrap();
+        if let (Some(width), Some(heigth)) = (page.width, page.heigth) {
+            write!(xml, " ImageWidth=\"{}\" ImageHeight=\"{}\"", width, heigth).unwrap();
+        }
+        xml.push_str(" />\n");
+    }
+    xml.push_str("  </Pages>\n");
+
+    xml.push_str("</ComicInfo>\n");
+
+    xml
+}
+
+/// Adds an element, empty values are left out.
+fn element(xml: &mut String, name: &str, value: &str) {
+    if !value.is_empty() {
+        // Writing to a String never fails
+        writeln!(xml, "  <{0}>{1}</{0}>", name, escape(value)).unwrap();
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// The ISO 639-1 code of the languages used by the supported sites.
+pub(crate) fn language_code(language: &str) -> Option<&'static str> {
+    let code = match language.to_lowercase().as_str() {
+        "chinese" => "zh",
+        "dutch" => "nl",
+        "english" => "en",
+        "french" => "fr",
+        "german" => "de",
+        "hungarian" => "hu",
+        "indonesian" => "id",
+        "italian" => "it",
+        "japanese" => "ja",
+        "korean" => "ko",
+        "polish" => "pl",
+        "portuguese" => "pt",
+        "russian" => "ru",
+        "spanish" => "es",
+        "thai" => "th",
+        "vietnamese" => "vi",
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comic() -> Comic {
+        serde_json::from_str(
+            r#"{
+                "authors": ["someone"],
+                "categories": ["Doujinshi"],
+                "characters": ["a character"],
+                "cover": { "file_name": "cover.jpg", "url": "https://ehgt.org/cover.jpg", "width": 250, "height": 350 },
+                "id": "2001",
+                "groups": ["a group"],
+                "languages": ["english"],
+                "pages": [
+                    { "file_name": "1.jpg", "url": "https://example.com/1.jpg", "width": 1280, "height": 1800 },
+                    { "file_name": "2.jpg", "url": "https://example.com/2.jpg" }
+                ],
+                "site": "e-hentai",
+                "tags": [{ "namespace": "female", "name": "glasses" }],
+                "title": "Tom & Jerry <3",
+                "translated": false,
+                "upload_date": 1600000000.0
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn url() -> Url {
+        "https://e-hentai.org/g/2001/0a1b2c3d4e/".parse().unwrap()
+    }
+
+    #[test]
+    fn schema_order() {
+        let xml = comic_info(&comic(), &url(), false);
+        let order = [
+            "Title",
+            "Series",
+            "Notes",
+            "Year",
+            "Month",
+            "Day",
+            "Writer",
+            "Genre",
+            "Tags",
+            "Web",
+            "PageCount",
+            "LanguageISO",
+            "Characters",
+            "Teams",
+            "Pages",
+        ];
+
+        let positions: Vec<usize> = order
+            .iter()
+            .map(|name| {
+                xml.find(&format!("<{}>", name))
+                    .unwrap_or_else(|| panic!("missing {}", name))
+            })
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+
+        assert!(xml.contains("<Web>https://e-hentai.org/g/2001/0a1b2c3d4e/</Web>"));
+        assert!(xml.contains("<Tags>female:glasses</Tags>"));
+        assert!(xml.contains("<LanguageISO>en</LanguageISO>"));
+    }
+
+    #[test]
+    fn escaped() {
+        let xml = comic_info(&comic(), &url(), false);
+
+        assert!(xml.contains("<Title>Tom &amp; Jerry &lt;3</Title>"));
+    }
+
+    #[test]
+    fn pages_with_cover() {
+        let xml = comic_info(&comic(), &url(), true);
+
+        assert!(xml.contains("<PageCount>3</PageCount>"));
+        assert!(xml.contains(
+            "  <Pages>\n    <Page Image=\"0\" Type=\"FrontCover\" ImageWidth=\"250\" ImageHeight=\"350\" />\n    <Page Image=\"1\" Type=\"Story\" ImageWidth=\"1280\" ImageHeight=\"1800\" />\n    <Page Image=\"2\" Type=\"Story\" />\n  </Pages>\n"
+        ));
+    }
+
+    #[test]
+    fn pages_without_cover() {
+        let xml = comic_info(&comic(), &url(), false);
+
+        assert!(xml.contains("<PageCount>2</PageCount>"));
+        assert!(xml.contains(
+            "  <Pages>\n    <Page Image=\"0\" Type=\"Story\" ImageWidth=\"1280\" ImageHeight=\"1800\" />\n    <Page Image=\"1\" Type=\"Story\" />\n  </Pages>\n"
+        ));
+    }
+}
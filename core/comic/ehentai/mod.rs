@@ -1,22 +1,125 @@
-use anyhow::Result;
+use async_trait::async_trait;
 use nipper::Document;
 use regex::RegexBuilder;
+use reqwest::{cookie::CookieStore, header::CONTENT_TYPE};
 use serde::{Deserialize, Serialize};
-use std::vec;
+use std::{convert::TryFrom, vec};
 use url::Url;
 
-use crate::comic::{Comic, Page};
+use crate::comic::{Comic, Page, Tag};
+use crate::error::{Error, Result};
+use crate::extractor::{host_matches, Extractor, Media};
+use crate::ratelimit::RateLimit;
+use crate::session::Session;
+
+/// Both the sites and the apis ask to stay around 4 requests per second.
+pub const RATE_LIMITS: &[(&str, RateLimit)] = &[
+    (
+        "e-hentai.org",
+        RateLimit {
+            per_second: 4.0,
+            burst: 4,
+        },
+    ),
+    (
+        "exhentai.org",
+        RateLimit {
+            per_second: 4.0,
+            burst: 4,
+        },
+    ),
+];
+
+/// The cookies of a logged in member, ExHentai also needs `igneous` which it sets itself.
+pub const MEMBER_COOKIES: &[&str] = &["ipb_member_id", "ipb_pass_hash"];
+
+/// How galleries are extracted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Download the original images instead of the resampled ones, as long as
+    /// the session is logged in and the image limit of the account allows it.
+    pub original: bool,
+}
+
+#[derive(Default)]
+pub struct EHentai {
+    pub options: Options,
+}
+
+#[async_trait]
+impl Extractor for EHentai {
+    fn name(&self) -> &'static str {
+        "e-hentai"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        host_matches(url, "e-hentai.org") && url.path().starts_with("/g/")
+    }
+
+    async fn extract(&self, session: &Session, url: &Url) -> Result<Media> {
+        Ok(Media::Comic(
+            get_comic(session, url.as_str(), &self.options).await?,
+        ))
+    }
+
+    fn comic_id(&self, url: &Url) -> Option<(&'static str, String)> {
+        gallery(url).map(|gallery| (self.name(), gallery.gid.to_string()))
+    }
+
+    async fn reload(&self, session: &Session, page: &Page) -> Result<Option<Page>> {
+        reload(session, page).await
+    }
+}
+
+/// The members only version of E-Hentai, it requires the cookies of an account with access.
+#[derive(Default)]
+pub struct ExHentai {
+    pub options: Options,
+}
+
+#[async_trait]
+impl Extractor for ExHentai {
+    fn name(&self) -> &'static str {
+        "exhentai"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        host_matches(url, "exhentai.org") && url.path().starts_with("/g/")
+    }
+
+    async fn extract(&self, session: &Session, url: &Url) -> Result<Media> {
+        Ok(Media::Comic(
+            get_comic(session, url.as_str(), &self.options).await?,
+        ))
+    }
+
+    fn comic_id(&self, url: &Url) -> Option<(&'static str, String)> {
+        gallery(url).map(|gallery| (self.name(), gallery.gid.to_string()))
+    }
+
+    async fn reload(&self, session: &Session, page: &Page) -> Result<Option<Page>> {
+        reload(session, page).await
+    }
+}
+
+/// The most galleries the api accepts in a single request.
+const GDATA_LIMIT: usize = 25;
+/// How many bytes of an original are estimated to cost one point of the image limit.
+const ORIGINAL_BYTES_PER_POINT: u64 = 100_000;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiRequest {
     pub method: String,
-    pub gidlist: Vec<(i32, String)>,
+    pub gidlist: Vec<(u32, String)>,
     pub namespace: i32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiResponse {
-    pub gmetadata: Vec<GMetaData>,
+    /// A [`GMetaData`] for every gallery asked, or an `error` for those the api can't find.
+    ///
+    /// The entries are parsed one by one so that a single odd gallery doesn't fail the others.
+    pub gmetadata: Vec<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,7 +134,7 @@ pub struct GMetaData {
     pub uploader: String,
     pub posted: String,
     pub filecount: String,
-    pub filesize: u32,
+    pub filesize: u64,
     pub expunged: bool,
     pub rating: String,
     #[serde(rename = "torrentcount")]
@@ -40,87 +143,411 @@ pub struct GMetaData {
     pub tags: Vec<String>,
 }
 
-pub async fn get_comic(url: &str) -> Result<Comic> {
-    let metadata = get_metadata(url).await?;
-    let resp = reqwest::get(url).await?.text().await?;
-    let document = Document::from(&resp);
+/// The image limit of an account, which original images count towards.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageLimits {
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl ImageLimits {
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used)
+    }
+}
+
+impl GMetaData {
+    pub fn gallery(&self) -> GalleryRef {
+        GalleryRef {
+            gid: self.gid,
+            token: self.token.clone(),
+        }
+    }
+}
+
+/// A gallery found in a list, enough to get its metadata or download it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GalleryRef {
+    pub gid: u32,
+    pub token: String,
+}
+
+impl GalleryRef {
+    /// The url of the gallery on E-Hentai.
+    pub fn url(&self) -> Result<Url> {
+        Ok(format!("https://e-hentai.org/g/{}/{}/", self.gid, self.token).parse()?)
+    }
+}
+
+/// The categories galleries are sorted into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Doujinshi,
+    Manga,
+    ArtistCg,
+    GameCg,
+    Western,
+    NonH,
+    ImageSet,
+    Cosplay,
+    AsianPorn,
+    Misc,
+}
+
+impl Category {
+    /// The bit of the category in the `f_cats` parameter of searches.
+    fn bit(self) -> u32 {
+        match self {
+            Category::Misc => 1,
+            Category::Doujinshi => 2,
+            Category::Manga => 4,
+            Category::ArtistCg => 8,
+            Category::GameCg => 16,
+            Category::ImageSet => 32,
+            Category::Cosplay => 64,
+            Category::AsianPorn => 128,
+            Category::NonH => 256,
+            Category::Western => 512,
+        }
+    }
+}
+
+/// What galleries a search is narrowed down to, besides its query.
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilters {
+    /// Only galleries of these categories, all of them when empty.
+    pub categories: Vec<Category>,
+    /// From 2 to 5 stars.
+    pub min_rating: Option<u8>,
+    pub min_pages: Option<u32>,
+    pub max_pages: Option<u32>,
+    /// The name of the language, e.g. `english`.
+    pub language: Option<String>,
+    /// Tags every gallery must have.
+    pub tags: Vec<Tag>,
+    /// Stops after this many galleries, searches can list thousands.
+    pub limit: Option<usize>,
+}
+
+/// The metadata of many galleries, sorted by what the api answered.
+#[derive(Debug, Default)]
+pub struct Batch {
+    pub galleries: Vec<GMetaData>,
+    /// Galleries removed from the site, only members can still see them.
+    pub expunged: Vec<GMetaData>,
+    /// The galleries the api refused, usually because of a wrong token, or
+    /// whose metadata can't be read, and why.
+    pub invalid: Vec<(GalleryRef, String)>,
+}
+
+impl Batch {
+    /// Sorts an entry of the answer of the api to a request for the `asked` galleries.
+    fn add(&mut self, asked: &[GalleryRef], entry: serde_json::Value) {
+        // Errors only give the id back
+        let gid = entry["gid"]
+            .as_u64()
+            .and_then(|gid| u32::try_from(gid).ok())
+            .unwrap_or_default();
+        let gallery = asked
+            .iter()
+            .find(|gallery| gallery.gid == gid)
+            .cloned()
+            .unwrap_or(GalleryRef {
+                gid,
+                token: String::new(),
+            });
+
+        if let Some(error) = entry["error"].as_str() {
+            self.invalid.push((gallery, error.to_string()));
+            return;
+        }
+
+        match serde_json::from_value::<GMetaData>(entry) {
+            Ok(metadata) if metadata.expunged => self.expunged.push(metadata),
+            Ok(metadata) => self.galleries.push(metadata),
+            Err(error) => self.invalid.push((gallery, error.to_string())),
+        }
+    }
+}
+
+/// Searches E-Hentai like its front page does, walking every page of results.
+pub async fn search(session: &Session, query: &str, filters: &SearchFilters) -> Result<Batch> {
+    let site = "e-hentai";
+    let limit = filters.limit.unwrap_or(usize::MAX);
+
+    let mut galleries: Vec<GalleryRef> = vec![];
+    let mut next = Some(search_url(query, filters)?);
+    let mut p = 0;
+
+    while let (Some(url), true) = (next, galleries.len() < limit) {
+        let resp = get_page(session, site, url.as_str()).await?;
+        session
+            .dump(site, &format!("search-p{}.html", p), &resp)
+            .await;
+        p += 1;
+
+        let (found, more) = search_results(&url, &resp)?;
+        for gallery in found {
+            if !galleries.contains(&gallery) {
+                galleries.push(gallery);
+            }
+        }
+        next = more;
+    }
+    galleries.truncate(limit);
+
+    get_metadata_batch(session, site, &galleries).await
+}
+
+fn search_url(query: &str, filters: &SearchFilters) -> Result<Url> {
+    let mut url: Url = "https://e-hentai.org/".parse()?;
+
+    // Exact tags are searched as `namespace:"tag$"`
+    let mut search = query.to_string();
+    if let Some(language) = &filters.language {
+        search.push_str(&format!(" language:\"{}$\"", language));
+    }
+    for tag in filters.tags.iter() {
+        match &tag.namespace {
+            Some(namespace) => search.push_str(&format!(" {}:\"{}$\"", namespace, tag.name)),
+            None => search.push_str(&format!(" \"{}$\"", tag.name)),
+        }
+    }
+
+    let mut pairs = url.query_pairs_mut();
+    pairs.append_pair("f_search", search.trim());
+
+    // The parameter holds the categories that are hidden
+    if !filters.categories.is_empty() {
+        let shown = filters
+            .categories
+            .iter()
+            .fold(0, |bits, category| bits | category.bit());
+        pairs.append_pair("f_cats", &(1023 & !shown).to_string());
+    }
+
+    let pages = filters.min_pages.is_some() || filters.max_pages.is_some();
+    if filters.min_rating.is_some() || pages {
+        pairs.append_pair("advsearch", "1");
+    }
+    if let Some(rating) = filters.min_rating {
+        pairs
+            .append_pair("f_sr", "on")
+            .append_pair("f_srdd", &rating.to_string());
+    }
+    if pages {
+        let bound = |pages: Option<u32>| pages.map(|pages| pages.to_string()).unwrap_or_default();
+        pairs
+            .append_pair("f_sp", "on")
+            .append_pair("f_spf", &bound(filters.min_pages))
+            .append_pair("f_spt", &bound(filters.max_pages));
+    }
+    drop(pairs);
+
+    Ok(url)
+}
+
+/// The galleries on a page of results and the url of the next page, if any.
+///
+/// Every display mode puts the results in `.itg`, as a table or as thumbnails.
+fn search_results(page: &Url, html: &str) -> Result<(Vec<GalleryRef>, Option<Url>)> {
+    let document = Document::from(html);
+    let gallery = RegexBuilder::new(r"/g/([0-9]+)/([0-9a-f]+)").build()?;
+
+    let mut galleries: Vec<GalleryRef> = vec![];
+    for link in document.select(r#".itg a[href*="/g/"]"#).iter() {
+        let href = link.attr("href").unwrap_or_default();
+        let c = match gallery.captures(&href) {
+            Some(c) => c,
+            None => continue,
+        };
+
+        let found = GalleryRef {
+            gid: c[1].parse().map_err(|_| Error::LayoutChanged {
+                site: "e-hentai",
+                what: "gallery id",
+            })?,
+            token: c[2].to_string(),
+        };
+        // Most modes link each gallery from its thumbnail and its title
+        if !galleries.contains(&found) {
+            galleries.push(found);
+        }
+    }
+
+    // Newer pages link the next one with a cursor, older ones with a page number
+    let next = match document
+        .select("a#unext, table.ptt td:last-child > a")
+        .attr("href")
+    {
+        Some(href) => Some(page.join(&href)?),
+        None => None,
+    };
+
+    Ok((galleries, next))
+}
+
+pub async fn get_comic(session: &Session, url: &str, options: &Options) -> Result<Comic> {
+    let site = site(url);
+    let mut original = options.original;
+    if site == "exhentai" {
+        login(session).await?;
+        check_login(session)?;
+    } else if original && login(session).await.is_err() {
+        // Originals are only served to members, the resampled images are still there
+        original = false;
+    }
+
+    let metadata = get_metadata(session, url).await?;
+
+    // The thumbnails are walked from the first page, whatever page the url points to
+    let mut gallery: Url = url.parse()?;
+    gallery.set_query(None);
+
+    let resp = get_page(session, site, gallery.as_str()).await?;
+    session.dump(site, "gallery.html", &resp).await;
 
     let mut authors: Vec<String> = vec![];
     let mut categories: Vec<String> = vec![];
     let mut characters: Vec<String> = vec![];
     let mut groups: Vec<String> = vec![];
     let mut languages: Vec<String> = vec![];
-    let mut tags: Vec<String> = vec![];
+    let mut tags: Vec<Tag> = vec![];
     let mut translated = false;
 
     for tag in metadata.tags {
-        match tag.split_once(':') {
-            None => tags.push(tag),
-            Some((namespace, tag)) => {
-                match namespace {
-                    "artist" => authors.push(tag.to_string()),
-                    "character" => characters.push(tag.to_string()),
-                    "female" => tags.push(tag.to_string()),
-                    "group" => groups.push(tag.to_string()),
-                    "language" => {
-                        if tag == "translated" {
-                            translated = true
-                        } else {
-                            languages.push(tag.to_string())
-                        }
-                    }
-                    "male" => tags.push(tag.to_string()),
-                    "parody" => categories.push(tag.to_string()),
-                    "reclass" => categories.push(tag.to_string()),
-                    _ => (), // TODO: Handle invalid namespace
-                }
-            }
+        let tag = Tag::parse(&tag);
+        let name = tag.name.clone();
+
+        // The other namespaces, like female or location, are only kept in the tags
+        match tag.namespace.as_deref() {
+            Some("artist") => authors.push(name),
+            Some("character") => characters.push(name),
+            Some("group") => groups.push(name),
+            Some("language") if name == "translated" => translated = true,
+            Some("language") => languages.push(name),
+            Some("parody") | Some("reclass") => categories.push(name),
+            _ => (),
         }
+
+        tags.push(tag);
     }
 
     let cover = {
         let url = metadata.thumb;
-        let file_name = url.path_segments().unwrap().last().unwrap().to_string();
+        let file_name = file_name(site, &url)?;
 
         Page {
+            fallback: None,
             file_name,
             heigth: None,
+            reload: None,
             url,
             width: None,
         }
     };
 
+    let mut links = thumbnail_links(site, &resp)?;
+    if links.is_empty() {
+        return Err(Error::LayoutChanged {
+            site,
+            what: "thumbnails",
+        });
+    }
+
+    // Every page of thumbnails has as many as the first one, except the last
+    let file_count: usize = metadata
+        .filecount
+        .parse()
+        .map_err(|_| Error::LayoutChanged {
+            site,
+            what: "filecount",
+        })?;
+    let thumbnail_pages = (file_count + links.len() - 1) / links.len();
+
+    for p in 1..thumbnail_pages {
+        gallery
+            .query_pairs_mut()
+            .clear()
+            .append_pair("p", &p.to_string());
+
+        let resp = get_page(session, site, gallery.as_str()).await?;
+        session
+            .dump(site, &format!("gallery-p{}.html", p), &resp)
+            .await;
+
+        let more = thumbnail_links(site, &resp)?;
+        if more.is_empty() {
+            break;
+        }
+        links.extend(more);
+    }
+
+    // The downloader still falls back to the resampled image if the limit is reached sooner
+    let cost = original_cost(metadata.filesize, file_count);
+    let mut remaining = if original {
+        image_limits(session)
+            .await?
+            .map_or(0, |limits| limits.remaining())
+    } else {
+        0
+    };
+
     let mut pages: Vec<Page> = vec![];
 
-    for s in document.select("#gdt > .gdtm > div > a").iter() {
-        let href = &s.attr("href").unwrap().to_string();
-        let resp = reqwest::get(href).await?.text().await?;
-        let url: Url = Document::from(&resp)
-            .select("#img")
-            .attr("src")
-            .unwrap()
-            .parse()?;
-        let file_name = url.path_segments().unwrap().last().unwrap().to_string();
-        // println!("{}", &file_name);
-
-        pages.push(Page {
-            file_name,
-            heigth: None,
-            url,
-            width: None,
-        })
+    for href in links {
+        let resp = get_page(session, site, &href).await?;
+        let ImagePage {
+            image: resampled,
+            original,
+            reload,
+        } = image_page(site, &href, &resp)?;
+        let file_name = file_name(site, &resampled)?;
+
+        let page = match original {
+            Some(original) if remaining >= cost => {
+                remaining -= cost;
+
+                Page {
+                    fallback: Some(resampled),
+                    // Only the newer links end with the name of the original
+                    file_name: if original.path().starts_with("/fullimg/") {
+                        self::file_name(site, &original)?
+                    } else {
+                        file_name
+                    },
+                    heigth: None,
+                    reload,
+                    url: original,
+                    width: None,
+                }
+            }
+            _ => Page {
+                fallback: None,
+                file_name,
+                heigth: None,
+                reload,
+                url: resampled,
+                width: None,
+            },
+        };
+
+        pages.push(page)
     }
 
     Ok(Comic {
         title: metadata.title,
         authors,
         tags,
-        upload_date: metadata.posted.parse().unwrap(),
+        upload_date: metadata.posted.parse().map_err(|_| Error::LayoutChanged {
+            site,
+            what: "posted date",
+        })?,
         languages,
         pages,
         cover,
         characters,
-        site: "e-hentai".to_string(),
+        site: site.to_string(),
         id: metadata.gid.to_string(),
         categories,
         translated,
@@ -128,28 +555,646 @@ pub async fn get_comic(url: &str) -> Result<Comic> {
     })
 }
 
-pub async fn get_metadata(url: &str) -> Result<GMetaData> {
-    let url_regex = RegexBuilder::new(r"https://e-hentai.org/g/([0-9]+)/([a-zA-Z0-9]+)").build()?;
-    let c = url_regex.captures(url).unwrap();
+pub async fn get_metadata(session: &Session, url: &str) -> Result<GMetaData> {
+    let gallery = Url::parse(url)
+        .ok()
+        .as_ref()
+        .and_then(gallery)
+        .ok_or_else(|| Error::UnsupportedUrl(url.to_string()))?;
+
+    let batch = get_metadata_batch(session, site(url), &[gallery]).await?;
+
+    batch
+        .galleries
+        .into_iter()
+        .chain(batch.expunged)
+        .next()
+        .ok_or_else(|| Error::NotFound(url.to_string()))
+}
+
+/// The metadata of any number of galleries of `site`, either `e-hentai` or `exhentai`.
+///
+/// They are asked [`GDATA_LIMIT`] at a time, one request after the other so that
+/// the rate limit of the api is respected.
+pub async fn get_metadata_batch(
+    session: &Session,
+    site: &str,
+    galleries: &[GalleryRef],
+) -> Result<Batch> {
+    let mut batch = Batch::default();
+
+    for chunk in galleries.chunks(GDATA_LIMIT) {
+        for entry in gdata(session, site, chunk).await? {
+            batch.add(chunk, entry);
+        }
+    }
+
+    Ok(batch)
+}
+
+/// The metadata of at most [`GDATA_LIMIT`] galleries.
+async fn gdata(
+    session: &Session,
+    site: &str,
+    galleries: &[GalleryRef],
+) -> Result<Vec<serde_json::Value>> {
+    // The api of ExHentai is only reachable by members, like the site
+    let api = match site {
+        "exhentai" => "https://s.exhentai.org/api.php",
+        _ => "https://api.e-hentai.org/api.php",
+    };
+
+    let res = session
+        .send(
+            session.post(api).json(&ApiRequest {
+                method: "gdata".to_string(),
+                gidlist: galleries
+                    .iter()
+                    .map(|gallery| (gallery.gid, gallery.token.clone()))
+                    .collect(),
+                namespace: 1,
+            }),
+        )
+        .await?
+        .text()
+        .await?;
+    session.dump(site, "gdata.json", &res).await;
+
+    let res: ApiResponse = serde_json::from_str(&res)?;
+
+    Ok(res.gmetadata)
+}
+
+/// The links to the image pages on a page of thumbnails.
+///
+/// Both the normal and the large thumbnails layouts are supported, they
+/// are chosen in the settings of the account.
+fn thumbnail_links(site: &'static str, html: &str) -> Result<Vec<String>> {
+    Document::from(html)
+        .select("#gdt > .gdtm > div > a, #gdt > .gdtl > a")
+        .iter()
+        .map(|s| {
+            s.attr("href")
+                .map(|href| href.to_string())
+                .ok_or(Error::LayoutChanged {
+                    site,
+                    what: "image page link",
+                })
+        })
+        .collect()
+}
+
+/// The urls found on the page of an image.
+struct ImagePage {
+    /// The resampled image, served by one of the H@H servers.
+    image: Url,
+    /// Only linked when it is bigger than the resampled image.
+    original: Option<Url>,
+    /// The same page, asking for the image from another server.
+    reload: Option<Url>,
+}
+
+fn image_page(site: &'static str, page: &str, html: &str) -> Result<ImagePage> {
+    let document = Document::from(html);
+    let page = Url::parse(page)?;
+
+    let image = document
+        .select("#img")
+        .attr("src")
+        .ok_or(Error::LayoutChanged { site, what: "#img" })?
+        .parse()?;
+    let original = match document.select("#i6 a[href*=\"fullimg\"]").attr("href") {
+        Some(href) => Some(page.join(&href)?),
+        None => None,
+    };
+
+    // The "Reload broken image" link calls `nl('key')`, which loads the page again with `nl=key`
+    let nl = RegexBuilder::new(r"nl\('([^']+)'\)").build()?;
+    let reload = document
+        .select("#loadfail")
+        .attr("onclick")
+        .and_then(|onclick| nl.captures(&onclick).map(|c| c[1].to_string()))
+        .map(|key| {
+            let mut reload = page.clone();
+            reload.query_pairs_mut().append_pair("nl", &key);
+            reload
+        });
+
+    Ok(ImagePage {
+        image,
+        original,
+        reload,
+    })
+}
+
+/// Loads the page of a broken image again, the site then serves it from another server.
+pub async fn reload(session: &Session, page: &Page) -> Result<Option<Page>> {
+    let href = match &page.reload {
+        Some(href) => href.as_str(),
+        None => return Ok(None),
+    };
+    let site = site(href);
+
+    let resp = get_page(session, site, href).await?;
+    session.dump(site, "reload.html", &resp).await;
+    let ImagePage { image, reload, .. } = image_page(site, href, &resp)?;
 
-    let gallery_id: i32 = c.get(1).unwrap().as_str().parse()?;
-    let gallery_token = c.get(2).unwrap().as_str();
+    Ok(Some(Page {
+        fallback: None,
+        file_name: page.file_name.clone(),
+        heigth: page.heigth,
+        reload,
+        url: image,
+        width: page.width,
+    }))
+}
 
-    let client = reqwest::Client::new();
-    let res: ApiResponse = client
-        .post("https://api.e-hentai.org/api.php")
-        .json(&ApiRequest {
-            method: "gdata".to_string(),
-            gidlist: vec![(gallery_id, gallery_token.to_string())],
-            namespace: 1,
+/// Reads the image limit of the account on its home page, visitors have none.
+pub async fn image_limits(session: &Session) -> Result<Option<ImageLimits>> {
+    let resp = session
+        .send(session.get("https://e-hentai.org/home.php"))
+        .await?
+        .text()
+        .await?;
+    session.dump("e-hentai", "home.html", &resp).await;
+
+    let regex = RegexBuilder::new(
+        r"You are currently at <strong>([0-9,]+)</strong> towards a limit of <strong>([0-9,]+)</strong>",
+    )
+    .build()?;
+    let number = |text: &str| text.replace(',', "").parse().ok();
+
+    Ok(regex.captures(&resp).and_then(|c| {
+        Some(ImageLimits {
+            used: number(&c[1])?,
+            limit: number(&c[2])?,
         })
-        .send()
+    }))
+}
+
+/// The points an original of a gallery is estimated to cost, from the average size of its files.
+///
+/// The site charges originals by size without telling how much, the estimate errs on the
+/// expensive side so that resampled images are used before the limit is actually reached.
+fn original_cost(filesize: u64, file_count: usize) -> u64 {
+    let average = filesize / file_count.max(1) as u64;
+
+    1 + (average + ORIGINAL_BYTES_PER_POINT - 1) / ORIGINAL_BYTES_PER_POINT
+}
+
+/// The gallery a url points to, `/g/{gid}/{token}/`.
+fn gallery(url: &Url) -> Option<GalleryRef> {
+    let mut segments = url.path_segments()?;
+    if segments.next()? != "g" {
+        return None;
+    }
+
+    let gid = segments.next()?.parse().ok()?;
+    let token = segments.next()?;
+    if token.is_empty() {
+        return None;
+    }
+
+    Some(GalleryRef {
+        gid,
+        token: token.to_string(),
+    })
+}
+
+/// The name of the site the url belongs to, used in [`Comic::site`] and errors.
+fn site(url: &str) -> &'static str {
+    match Url::parse(url) {
+        Ok(url) if host_matches(&url, "exhentai.org") => "exhentai",
+        _ => "e-hentai",
+    }
+}
+
+/// Makes sure the session has the member cookies ExHentai requires.
+///
+/// Members usually log in on the forums of E-Hentai, so cookies only set for
+/// e-hentai.org are copied to exhentai.org.
+fn check_login(session: &Session) -> Result<()> {
+    let exhentai: Url = "https://exhentai.org/".parse()?;
+    let ehentai: Url = "https://e-hentai.org/".parse()?;
+    let cookies = |url: &Url| cookies(session, url);
+
+    let existing = cookies(&exhentai);
+    for (name, value) in cookies(&ehentai) {
+        if MEMBER_COOKIES.contains(&name.as_str()) && !existing.iter().any(|(n, _)| *n == name) {
+            session.cookies().add_cookie_str(
+                &format!("{}={}; Domain=exhentai.org; Path=/", name, value),
+                &exhentai,
+            );
+        }
+    }
+
+    let cookies = cookies(&exhentai);
+    let has = |name: &str| cookies.iter().any(|(n, v)| n == name && !v.is_empty());
+    // ExHentai sets this value for accounts without access
+    let mystery = cookies
+        .iter()
+        .any(|(name, value)| name == "igneous" && value == "mystery");
+
+    if MEMBER_COOKIES.iter().all(|name| has(name)) && !mystery {
+        Ok(())
+    } else {
+        Err(Error::LoginRequired { site: "exhentai" })
+    }
+}
+
+/// Logs into the forums with the credentials given for either site, unless the
+/// session already has the member cookies, which the forums set.
+async fn login(session: &Session) -> Result<()> {
+    let ehentai: Url = "https://e-hentai.org/".parse()?;
+    let exhentai: Url = "https://exhentai.org/".parse()?;
+
+    let has_cookies = |cookies: Vec<(String, String)>| {
+        MEMBER_COOKIES
+            .iter()
+            .all(|name| cookies.iter().any(|(n, v)| n == name && !v.is_empty()))
+    };
+    if has_cookies(cookies(session, &ehentai)) || has_cookies(cookies(session, &exhentai)) {
+        return Ok(());
+    }
+
+    let credentials = match session
+        .credentials(&ehentai)
+        .or_else(|| session.credentials(&exhentai))
+    {
+        Some(credentials) => credentials,
+        None => return Ok(()),
+    };
+
+    let form = [
+        ("referer", "https://forums.e-hentai.org/index.php"),
+        ("b", ""),
+        ("bt", ""),
+        ("UserName", &credentials.username),
+        ("PassWord", &credentials.password),
+        ("CookieDate", "1"),
+    ];
+    let resp = session
+        .send(
+            session
+                .post("https://forums.e-hentai.org/index.php?act=Login&CODE=01")
+                .form(&form),
+        )
         .await?
-        .json()
+        .text()
         .await?;
+    session.dump("e-hentai", "login.html", &resp).await;
+
+    // Wrong credentials and captchas both leave the cookies unset
+    if has_cookies(cookies(session, &ehentai)) {
+        Ok(())
+    } else {
+        Err(Error::LoginRequired { site: "e-hentai" })
+    }
+}
+
+/// The cookies the session sends to the url, as names and values.
+fn cookies(session: &Session, url: &Url) -> Vec<(String, String)> {
+    session
+        .cookies()
+        .cookies(url)
+        .and_then(|header| header.to_str().ok().map(str::to_string))
+        .unwrap_or_default()
+        .split("; ")
+        .filter_map(|cookie| {
+            let (name, value) = cookie.split_once('=')?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Fetches a page of the site, ExHentai answers requests without valid
+/// member cookies with an empty page or the image of a sad panda.
+async fn get_page(session: &Session, site: &str, url: &str) -> Result<String> {
+    let response = session.send(session.get(url)).await?;
+
+    let image = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("image/"));
+    let text = response.text().await?;
+
+    if site == "exhentai" && (image || text.trim().is_empty()) {
+        return Err(Error::LoginRequired { site: "exhentai" });
+    }
+
+    Ok(text)
+}
+
+fn file_name(site: &'static str, url: &Url) -> Result<String> {
+    url.path_segments()
+        .and_then(|segments| segments.last())
+        .map(|name| name.to_string())
+        .ok_or(Error::LayoutChanged {
+            site,
+            what: "image file name",
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The compact mode, a table with a row per gallery and the newer pager.
+    const TABLE: &str = r#"<html><body>
+        <table class="itg gltc">
+            <tr><th>Published</th><th>Title</th></tr>
+            <tr>
+                <td class="gl2c"><div class="glthumb"><a href="https://e-hentai.org/g/2001/0a1b2c3d4e/"><img src="t.jpg"></a></div></td>
+                <td class="gl3c glname"><a href="https://e-hentai.org/g/2001/0a1b2c3d4e/"><div class="glink">First</div></a></td>
+            </tr>
+            <tr>
+                <td class="gl3c glname"><a href="https://e-hentai.org/g/2002/5f6a7b8c9d/"><div class="glink">Second</div></a></td>
+                <td class="gl4c glhide"><a href="https://e-hentai.org/uploader/someone">someone</a></td>
+            </tr>
+        </table>
+        <div class="searchnav">
+            <a id="uprev" href="https://e-hentai.org/?prev=2003">&lt; Prev</a>
+            <a id="unext" href="https://e-hentai.org/?next=2002">Next &gt;</a>
+        </div>
+    </body></html>"#;
+
+    /// The thumbnail mode, a grid of galleries and the older pager.
+    const THUMBNAILS: &str = r#"<html><body>
+        <table class="ptt"><tr>
+            <td class="ptds"><a href="?page=0">1</a></td>
+            <td><a href="?page=1">2</a></td>
+            <td><a href="?page=1">&gt;</a></td>
+        </tr></table>
+        <div class="itg gld">
+            <div class="gl1t">
+                <a href="https://e-hentai.org/g/3001/aaaaaaaaaa/"><span class="glink">Third</span></a>
+                <div class="gl3t"><a href="https://e-hentai.org/g/3001/aaaaaaaaaa/"><img src="t.jpg"></a></div>
+            </div>
+            <div class="gl1t">
+                <a href="https://e-hentai.org/g/3002/bbbbbbbbbb/"><span class="glink">Fourth</span></a>
+            </div>
+        </div>
+    </body></html>"#;
+
+    /// The last page has nothing to go to next.
+    const LAST: &str = r#"<html><body>
+        <table class="ptt"><tr>
+            <td><a href="?page=0">1</a></td>
+            <td class="ptds"><a href="?page=1">2</a></td>
+            <td class="ptdd">&gt;</td>
+        </tr></table>
+        <div class="itg gld">
+            <div class="gl1t"><a href="https://e-hentai.org/g/3003/cccccccccc/">Fifth</a></div>
+        </div>
+    </body></html>"#;
+
+    /// The normal thumbnails, sprites inside a div.
+    const NORMAL_THUMBNAILS: &str = r#"<html><body>
+        <table class="ptt"><tr><td><a href="https://e-hentai.org/g/2001/0a1b2c3d4e/?p=1">2</a></td></tr></table>
+        <div id="gdt">
+            <div class="gdtm" style="height:170px"><div style="margin:1px auto 0; width:100px; height:143px; background:transparent url(https://ehgt.org/m/1.jpg) -0px 0 no-repeat">
+                <a href="https://e-hentai.org/s/aaaaaaaaaa/2001-1"><img alt="01" title="Page 1: 1.jpg" src="https://ehgt.org/g/blank.gif" /></a>
+            </div></div>
+            <div class="gdtm" style="height:170px"><div style="margin:1px auto 0; width:100px; height:143px; background:transparent url(https://ehgt.org/m/1.jpg) -100px 0 no-repeat">
+                <a href="https://e-hentai.org/s/bbbbbbbbbb/2001-2"><img alt="02" title="Page 2: 2.jpg" src="https://ehgt.org/g/blank.gif" /></a>
+            </div></div>
+            <div class="c"></div>
+        </div>
+    </body></html>"#;
+
+    /// The large thumbnails, an image per page.
+    const LARGE_THUMBNAILS: &str = r#"<html><body>
+        <div id="gdt">
+            <div class="gdtl" style="height:320px"><a href="https://e-hentai.org/s/cccccccccc/2001-1"><img alt="01" title="Page 1: 1.jpg" src="https://ehgt.org/l/1.jpg" /></a></div>
+            <div class="gdtl" style="height:320px"><a href="https://e-hentai.org/s/dddddddddd/2001-2"><img alt="02" title="Page 2: 2.jpg" src="https://ehgt.org/l/2.jpg" /></a></div>
+            <div class="c"></div>
+        </div>
+    </body></html>"#;
+
+    fn gallery(gid: u32, token: &str) -> GalleryRef {
+        GalleryRef {
+            gid,
+            token: token.to_string(),
+        }
+    }
+
+    #[test]
+    fn search_url_query() {
+        let filters = SearchFilters {
+            language: Some("english".to_string()),
+            tags: vec![Tag::parse("female:glasses"), Tag::parse("full color")],
+            ..SearchFilters::default()
+        };
+
+        let url = search_url("title", &filters).unwrap();
+        let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert_eq!(
+            pairs,
+            vec![(
+                "f_search".to_string(),
+                r#"title language:"english$" female:"glasses$" "full color$""#.to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn search_url_filters() {
+        let filters = SearchFilters {
+            categories: vec![Category::Doujinshi, Category::Manga],
+            min_rating: Some(4),
+            min_pages: Some(10),
+            ..SearchFilters::default()
+        };
+
+        let url = search_url("", &filters).unwrap();
+        assert_eq!(
+            url.query(),
+            Some("f_search=&f_cats=1017&advsearch=1&f_sr=on&f_srdd=4&f_sp=on&f_spf=10&f_spt=")
+        );
 
-    // If the first element does not exist we should handle this in a better way
-    let metadata = res.gmetadata.into_iter().nth(0).unwrap();
+        let filters = SearchFilters {
+            max_pages: Some(50),
+            ..SearchFilters::default()
+        };
+        let url = search_url("", &filters).unwrap();
+        assert_eq!(
+            url.query(),
+            Some("f_search=&advsearch=1&f_sp=on&f_spf=&f_spt=50")
+        );
+    }
+
+    #[test]
+    fn search_results_table() {
+        let page: Url = "https://e-hentai.org/?f_search=title".parse().unwrap();
+        let (galleries, next) = search_results(&page, TABLE).unwrap();
+
+        assert_eq!(
+            galleries,
+            vec![gallery(2001, "0a1b2c3d4e"), gallery(2002, "5f6a7b8c9d")]
+        );
+        assert_eq!(next.unwrap().as_str(), "https://e-hentai.org/?next=2002");
+    }
+
+    #[test]
+    fn search_results_thumbnails() {
+        let page: Url = "https://e-hentai.org/?f_search=title".parse().unwrap();
+        let (galleries, next) = search_results(&page, THUMBNAILS).unwrap();
+
+        assert_eq!(
+            galleries,
+            vec![gallery(3001, "aaaaaaaaaa"), gallery(3002, "bbbbbbbbbb")]
+        );
+        assert_eq!(next.unwrap().as_str(), "https://e-hentai.org/?page=1");
+    }
+
+    #[test]
+    fn search_results_last_page() {
+        let page: Url = "https://e-hentai.org/?page=1".parse().unwrap();
+        let (galleries, next) = search_results(&page, LAST).unwrap();
+
+        assert_eq!(galleries, vec![gallery(3003, "cccccccccc")]);
+        assert!(next.is_none());
+    }
+
+    fn metadata(gid: u32, token: &str, expunged: bool) -> serde_json::Value {
+        serde_json::json!({
+            "gid": gid,
+            "token": token,
+            "archiver_key": "key",
+            "title": "title",
+            "title_jpn": "",
+            "category": "Doujinshi",
+            "thumb": "https://ehgt.org/t.jpg",
+            "uploader": "someone",
+            "posted": "1600000000",
+            "filecount": "20",
+            // Larger than a u32
+            "filesize": 5_000_000_000u64,
+            "expunged": expunged,
+            "rating": "4.5",
+            "torrentcount": "0",
+            "torrents": [],
+            "tags": ["female:glasses"]
+        })
+    }
+
+    #[test]
+    fn batch_sorts_entries() {
+        let asked = [
+            gallery(1, "aaaaaaaaaa"),
+            gallery(2, "bbbbbbbbbb"),
+            gallery(3, "cccccccccc"),
+            gallery(4, "dddddddddd"),
+        ];
+        let entries = vec![
+            metadata(1, "aaaaaaaaaa", false),
+            metadata(2, "bbbbbbbbbb", true),
+            serde_json::json!({ "gid": 3, "error": "Key missing, or incorrect key provided." }),
+            serde_json::json!({ "gid": 4, "token": "dddddddddd", "title": "missing fields" }),
+        ];
+
+        let mut batch = Batch::default();
+        for entry in entries {
+            batch.add(&asked, entry);
+        }
 
-    Ok(metadata)
+        assert_eq!(batch.galleries.len(), 1);
+        assert_eq!(batch.galleries[0].gallery(), asked[0]);
+        assert_eq!(batch.galleries[0].filesize, 5_000_000_000);
+        assert_eq!(batch.expunged.len(), 1);
+        assert_eq!(batch.expunged[0].gallery(), asked[1]);
+
+        assert_eq!(batch.invalid.len(), 2);
+        assert_eq!(
+            batch.invalid[0],
+            (
+                asked[2].clone(),
+                "Key missing, or incorrect key provided.".to_string()
+            )
+        );
+        assert_eq!(batch.invalid[1].0, asked[3]);
+    }
+
+    #[test]
+    fn original_costs() {
+        // At least one point, like any image
+        assert_eq!(original_cost(0, 10), 1);
+        assert_eq!(original_cost(1_000_000, 10), 2);
+        assert_eq!(original_cost(1_000_010, 10), 3);
+        assert_eq!(original_cost(30_000_000, 10), 31);
+        assert_eq!(original_cost(1_000_000, 0), 11);
+    }
+
+    #[test]
+    fn thumbnail_links_normal() {
+        assert_eq!(
+            thumbnail_links("e-hentai", NORMAL_THUMBNAILS).unwrap(),
+            vec![
+                "https://e-hentai.org/s/aaaaaaaaaa/2001-1",
+                "https://e-hentai.org/s/bbbbbbbbbb/2001-2"
+            ]
+        );
+    }
+
+    #[test]
+    fn thumbnail_links_large() {
+        assert_eq!(
+            thumbnail_links("e-hentai", LARGE_THUMBNAILS).unwrap(),
+            vec![
+                "https://e-hentai.org/s/cccccccccc/2001-1",
+                "https://e-hentai.org/s/dddddddddd/2001-2"
+            ]
+        );
+    }
+
+    #[test]
+    fn image_page_links() {
+        let html = r##"<html><body>
+            <div id="i3"><a onclick="return load_image(2, 'bbbbbbbbbb')" href="https://e-hentai.org/s/bbbbbbbbbb/2001-2">
+                <img id="img" src="https://abc.hath.network:1234/h/key/keystamp=1;fileindex=2;xres=1280/1.jpg" />
+            </a></div>
+            <div id="i6">
+                <a id="loadfail" href="#" onclick="return nl('12345-67890')">Reload broken image</a>
+                <a href="https://e-hentai.org/fullimg.php?gid=2001&amp;page=1&amp;key=abc">Download original 2400 x 3400 3.2 MB source</a>
+            </div>
+        </body></html>"##;
+
+        let page =
+            image_page("e-hentai", "https://e-hentai.org/s/aaaaaaaaaa/2001-1", html).unwrap();
+        assert_eq!(
+            page.image.as_str(),
+            "https://abc.hath.network:1234/h/key/keystamp=1;fileindex=2;xres=1280/1.jpg"
+        );
+        assert_eq!(
+            page.original.unwrap().as_str(),
+            "https://e-hentai.org/fullimg.php?gid=2001&page=1&key=abc"
+        );
+        assert_eq!(
+            page.reload.unwrap().as_str(),
+            "https://e-hentai.org/s/aaaaaaaaaa/2001-1?nl=12345-67890"
+        );
+    }
+
+    #[test]
+    fn image_page_without_original() {
+        let html = r#"<html><body>
+            <img id="img" src="https://ehgt.org/1.jpg" />
+            <div id="i6"><a href="https://e-hentai.org/s/aaaaaaaaaa/2001-1">Report</a></div>
+        </body></html>"#;
+
+        let page =
+            image_page("e-hentai", "https://e-hentai.org/s/aaaaaaaaaa/2001-1", html).unwrap();
+        assert!(page.original.is_none());
+        assert!(page.reload.is_none());
+
+        assert!(matches!(
+            image_page(
+                "e-hentai",
+                "https://e-hentai.org/s/aaaaaaaaaa/2001-1",
+                "<html></html>"
+            ),
+            Err(Error::LayoutChanged { what: "#img", .. })
+        ));
+    }
 }
@@ -1,11 +1,14 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use url::Url;
 
+pub mod comicinfo;
 pub mod ehentai;
 pub mod nhentai;
 pub mod pixiv;
 
 /// A struct containing vital information about a comic.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Comic {
     /// The authors of the comic
     pub authors: Vec<String>,
@@ -27,7 +30,9 @@ pub struct Comic {
     pub pages: Vec<Page>,
     /// The site this comic was downloaded from
     pub site: String,
-    pub tags: Vec<String>,
+    /// Every tag of the comic, including those also found in other fields like
+    /// the authors, so that the namespaces of the site are never lost.
+    pub tags: Vec<Tag>,
     pub title: String,
     /// Whether or not the comic was translated from another language.
     pub translated: bool,
@@ -35,15 +40,56 @@ pub struct Comic {
     pub upload_date: f64,
 }
 
-#[derive(Debug)]
+/// A tag with the namespace the site puts it in, e.g. `female:glasses`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Tag {
+    /// `None` for tags without namespace.
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+impl Tag {
+    pub fn new(namespace: Option<&str>, name: &str) -> Self {
+        Tag {
+            namespace: namespace.map(str::to_string),
+            name: name.to_string(),
+        }
+    }
+
+    /// Parses a tag written as `namespace:name` or just `name`.
+    pub fn parse(tag: &str) -> Self {
+        match tag.split_once(':') {
+            Some((namespace, name)) => Tag::new(Some(namespace), name),
+            None => Tag::new(None, tag),
+        }
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.namespace {
+            Some(namespace) => write!(f, "{}:{}", namespace, self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Page {
+    /// Downloaded instead of `url` when it fails, e.g. the resampled version of an original.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<Url>,
     pub file_name: String,
+    #[serde(rename = "height")]
     pub heigth: Option<u32>,
+    /// Where to ask for another url when the page fails, see [`crate::Extractor::reload`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reload: Option<Url>,
     pub url: Url,
     pub width: Option<u32>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Image {
     pub url: Url,
 }
@@ -1,13 +1,44 @@
-use anyhow::Result;
+use async_trait::async_trait;
 use url::Url;
 
 pub mod reader;
 use reader::Reader;
 
-use crate::comic::{Comic, Page};
+use crate::comic::{Comic, Page, Tag};
+use crate::error::Result;
+use crate::extractor::{host_matches, Extractor, Media};
+use crate::session::Session;
 
-pub async fn get_comic(url: &str) -> Result<Comic> {
-    let resp = reqwest::get(url).await?.text().await?;
+pub struct NHentai;
+
+#[async_trait]
+impl Extractor for NHentai {
+    fn name(&self) -> &'static str {
+        "nhentai"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        host_matches(url, "nhentai.net") && url.path().starts_with("/g/")
+    }
+
+    async fn extract(&self, session: &Session, url: &Url) -> Result<Media> {
+        Ok(Media::Comic(get_comic(session, url.as_str()).await?))
+    }
+
+    fn comic_id(&self, url: &Url) -> Option<(&'static str, String)> {
+        // `/g/{id}/`
+        let id = url.path_segments()?.nth(1)?;
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        Some((self.name(), id.to_string()))
+    }
+}
+
+pub async fn get_comic(session: &Session, url: &str) -> Result<Comic> {
+    let resp = session.get_text(url).await?;
+    session.dump("nhentai", "gallery.html", &resp).await;
     let reader = Reader::from_page(&resp)?;
 
     let cover: Page = {
@@ -23,6 +54,8 @@ pub async fn get_comic(url: &str) -> Result<Comic> {
         );
 
         Page {
+            fallback: None,
+            reload: None,
             url,
             width: Some(reader.gallery.images.cover.width),
             heigth: Some(reader.gallery.images.cover.heigth),
@@ -45,6 +78,8 @@ pub async fn get_comic(url: &str) -> Result<Comic> {
         let file_name = format!("{}.{}", index + 1, page.image_type.to_string());
 
         pages.push(Page {
+            fallback: None,
+            reload: None,
             url,
             width: Some(page.width),
             heigth: Some(page.heigth),
@@ -57,13 +92,15 @@ pub async fn get_comic(url: &str) -> Result<Comic> {
     let mut characters: Vec<String> = vec![];
     let mut groups: Vec<String> = vec![];
     let mut languages: Vec<String> = vec![];
-    let mut tags: Vec<String> = vec![];
+    let mut tags: Vec<Tag> = vec![];
     let mut translated: bool = false;
 
     for tag in reader.gallery.tags {
+        tags.push(Tag::new(tag.tag_type.namespace(), &tag.name));
+
         let name = tag.name;
         match tag.tag_type {
-            reader::TagType::Tag => tags.push(name),
+            reader::TagType::Tag => (),
             reader::TagType::Language => {
                 if name == "translated" {
                     translated = true
@@ -1,9 +1,10 @@
-use anyhow::Result;
 use regex::RegexBuilder;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use url::Url;
 
+use crate::error::{Error, Result};
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Reader {
     pub media_url: Url,
@@ -86,6 +87,21 @@ pub enum TagType {
     Category,
 }
 
+impl TagType {
+    /// The namespace of the tags of this type in [`crate::comic::Tag`].
+    pub fn namespace(&self) -> Option<&'static str> {
+        match self {
+            TagType::Tag => None,
+            TagType::Language => Some("language"),
+            TagType::Parody => Some("parody"),
+            TagType::Character => Some("character"),
+            TagType::Group => Some("group"),
+            TagType::Artist => Some("artist"),
+            TagType::Category => Some("category"),
+        }
+    }
+}
+
 impl Reader {
     pub fn from_page(page: &str) -> Result<Reader> {
         let gallery = Gallery::from_page(page)?;
@@ -95,19 +111,22 @@ impl Reader {
 
         let media_url: Url = media_url_regex
             .captures(page)
-            .unwrap()
-            .get(1)
-            .unwrap()
+            .and_then(|c| c.get(1))
+            .ok_or(Error::LayoutChanged {
+                site: "nhentai",
+                what: "media_url",
+            })?
             .as_str()
             .parse()?;
 
         let start_page: u32 = start_page_regex
             .captures(page)
-            .unwrap()
-            .get(1)
-            .unwrap()
-            .as_str()
-            .parse()?;
+            .and_then(|c| c.get(1))
+            .and_then(|m| m.as_str().trim().parse().ok())
+            .ok_or(Error::LayoutChanged {
+                site: "nhentai",
+                what: "start_page",
+            })?;
 
         Ok(Reader {
             media_url,
@@ -125,9 +144,11 @@ impl Gallery {
 
         let gallery = gallery_regex
             .captures(page)
-            .unwrap()
-            .get(1)
-            .unwrap()
+            .and_then(|c| c.get(1))
+            .ok_or(Error::LayoutChanged {
+                site: "nhentai",
+                what: "window._gallery",
+            })?
             .as_str()
             .replace(r"\u0022", r#"""#);
 
@@ -1,30 +1,165 @@
-use anyhow::Result;
+use async_trait::async_trait;
+use chrono::DateTime;
 use nipper::Document;
-use reqwest::ClientBuilder;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::Path};
-use tokio::fs;
+use std::collections::HashMap;
 use url::Url;
 
-pub async fn get_comic(url: &str) -> Result<()> {
-    let resp = reqwest::get(url).await?.text().await?;
-    fs::write("temp/pixi.html", &resp).await?;
+use crate::comic::{self, Comic, Page};
+use crate::error::{Error, Result};
+use crate::extractor::{host_matches, Extractor, Media};
+use crate::ratelimit::RateLimit;
+use crate::session::Session;
 
-    let document = Document::from(&resp);
-    let data = document
+/// pximg.net refuses to serve images to requests without a referer from pixiv.
+pub const HEADERS: &[(&str, &str, &str)] = &[("pximg.net", "Referer", "https://www.pixiv.net/")];
+
+/// Pixiv is quick to flag accounts that browse too fast.
+pub const RATE_LIMITS: &[(&str, RateLimit)] = &[
+    (
+        "pixiv.net",
+        RateLimit {
+            per_second: 2.0,
+            burst: 2,
+        },
+    ),
+    (
+        "pximg.net",
+        RateLimit {
+            per_second: 5.0,
+            burst: 5,
+        },
+    ),
+];
+
+pub struct Pixiv;
+
+#[async_trait]
+impl Extractor for Pixiv {
+    fn name(&self) -> &'static str {
+        "pixiv"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        host_matches(url, "pixiv.net") && url.path().contains("/artworks/")
+    }
+
+    async fn extract(&self, session: &Session, url: &Url) -> Result<Media> {
+        Ok(Media::Comic(get_comic(session, url.as_str()).await?))
+    }
+
+    fn comic_id(&self, url: &Url) -> Option<(&'static str, String)> {
+        // `/artworks/{id}`, sometimes after a language like `/en/`
+        let mut segments = url
+            .path_segments()?
+            .skip_while(|segment| *segment != "artworks");
+        let id = segments.nth(1)?;
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        Some((self.name(), id.to_string()))
+    }
+}
+
+pub async fn get_comic(session: &Session, url: &str) -> Result<Comic> {
+    let resp = session.get_text(url).await?;
+    session.dump("pixiv", "page.html", &resp).await;
+
+    // Documents can't be kept across await points, extractors must be Send
+    let data = Document::from(&resp)
         .select("#meta-preload-data")
         .attr("content")
-        .unwrap()
+        .ok_or(Error::LayoutChanged {
+            site: "pixiv",
+            what: "#meta-preload-data",
+        })?
         .to_string();
 
-    fs::write("temp/p.c.json", &data).await?;
+    session.dump("pixiv", "preload_data.json", &data).await;
 
     let config: PixivConfig = serde_json::from_str(&data)?;
-    fs::write("temp/config.p.json", serde_json::to_string_pretty(&config)?).await?;
 
-    println!("{:#?}", &config);
+    let illust = config
+        .illust
+        .into_iter()
+        .map(|(_, illust)| illust)
+        .next()
+        .ok_or_else(|| Error::NotFound(url.to_string()))?;
+
+    let mut pages: Vec<Page> = vec![];
+
+    // Only the url of the first page is available, the others differ only by the page index
+    let first_page = illust.urls.original.as_str();
+
+    for index in 0..illust.page_count {
+        let url: Url = first_page
+            .replacen("_p0.", &format!("_p{}.", index), 1)
+            .parse()?;
+        let file_name = url
+            .path_segments()
+            .and_then(|segments| segments.last())
+            .unwrap_or_default()
+            .to_string();
+
+        // Only the size of the first page is known
+        let (width, heigth) = match index {
+            0 => (Some(illust.width), Some(illust.height)),
+            _ => (None, None),
+        };
+
+        pages.push(Page {
+            fallback: None,
+            file_name,
+            heigth,
+            reload: None,
+            url,
+            width,
+        })
+    }
+
+    let cover = {
+        let url = illust.urls.regular;
+        let file_name = format!("cover.{}", url.path().rsplit('.').next().unwrap_or("jpg"));
+
+        Page {
+            fallback: None,
+            file_name,
+            heigth: None,
+            reload: None,
+            url,
+            width: None,
+        }
+    };
+
+    // Pixiv tags have no namespace
+    let tags = illust
+        .tags
+        .tags
+        .iter()
+        .map(|tag| comic::Tag::new(None, &tag.tag))
+        .collect();
 
-    Ok(())
+    Ok(Comic {
+        authors: vec![illust.user_name],
+        categories: vec![],
+        characters: vec![],
+        cover,
+        id: illust.illust_id,
+        groups: vec![],
+        languages: vec![],
+        pages,
+        site: "pixiv".to_string(),
+        tags,
+        title: illust.illust_title,
+        translated: false,
+        upload_date: DateTime::parse_from_rfc3339(&illust.upload_date)
+            .map_err(|_| Error::LayoutChanged {
+                site: "pixiv",
+                what: "uploadDate",
+            })?
+            .timestamp() as f64,
+    })
 }
 
 #[derive(Debug, Serialize, Deserialize)]
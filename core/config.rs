@@ -0,0 +1,279 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    cookies::CookieSource,
+    download::{DownloadOptions, Format},
+    error::Result,
+    extractor::ExtractorOptions,
+    ratelimit::RateLimit,
+    retry::RetryPolicy,
+    session::{Credentials, SessionBuilder},
+};
+
+/// The settings read from the config file, everything left out keeps its default.
+///
+/// ```toml
+/// output = "/home/me/comics"
+/// format = "cbz"
+/// concurrency = 8
+/// proxy = "socks5://127.0.0.1:9050"
+///
+/// [sites."e-hentai.org"]
+/// format = "folder"
+/// original = true
+/// cookies = { ipb_member_id = "123", ipb_pass_hash = "abc" }
+/// rate_limit = { per_second = 1.0, burst = 2 }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The directory everything is downloaded into.
+    pub output: Option<PathBuf>,
+    /// Where each page goes inside the output directory, see [`crate::output::render`].
+    pub template: Option<String>,
+    /// The format comics are stored in when their site has no preference.
+    pub format: Option<Format>,
+    pub comic_info: Option<bool>,
+    pub metadata: Option<bool>,
+    pub concurrency: Option<usize>,
+    pub per_host: Option<usize>,
+    pub proxy: Option<String>,
+    pub user_agent: Option<String>,
+    /// In seconds.
+    pub connect_timeout: Option<u64>,
+    /// In seconds, see [`SessionBuilder::read_timeout`].
+    pub read_timeout: Option<u64>,
+    /// Maximum number of times a failed request is retried.
+    pub retries: Option<u32>,
+    /// In seconds, the longest a site asking to wait with `Retry-After` is waited for.
+    pub max_retry_after: Option<u64>,
+    /// A `cookies.txt` to import cookies from.
+    pub cookies_file: Option<PathBuf>,
+    /// The browser to import cookies from, optionally followed by the profile, e.g. `firefox`.
+    pub cookies_from_browser: Option<CookieSource>,
+    /// Settings for a domain and its subdomains.
+    pub sites: HashMap<String, SiteConfig>,
+    /// The keys in the file that are not settings, most likely typos.
+    #[serde(skip)]
+    pub ignored: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SiteConfig {
+    /// The format comics of this site are stored in.
+    pub format: Option<Format>,
+    /// Download original images instead of resampled ones, on the sites that have both.
+    pub original: Option<bool>,
+    /// Logs into the site when the cookies of a member are missing, only
+    /// E-Hentai and ExHentai use them for now.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Cookies sent to the site, usually the ones of a logged in browser.
+    pub cookies: HashMap<String, String>,
+    pub rate_limit: Option<RateLimit>,
+    pub retries: Option<u32>,
+}
+
+impl Config {
+    /// Where the config file is looked for when none is given,
+    /// `$XDG_CONFIG_HOME/yuyu/config.toml` on Linux.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|directory| directory.join("yuyu").join("config.toml"))
+    }
+
+    /// Loads the config file at the default path, if there is one.
+    pub fn load_default() -> Result<Self> {
+        match Config::default_path() {
+            Some(path) if path.exists() => Config::load(path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+
+        Config::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut ignored = vec![];
+        let mut config: Config =
+            serde_ignored::deserialize(&mut toml::Deserializer::new(text), |path| {
+                ignored.push(path.to_string())
+            })?;
+        config.ignored = ignored;
+
+        Ok(config)
+    }
+
+    /// A session builder with the network settings of the config.
+    pub fn session_builder(&self) -> SessionBuilder {
+        let mut builder = SessionBuilder::new();
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(Duration::from_secs(timeout));
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.read_timeout(Duration::from_secs(timeout));
+        }
+        if self.retries.is_some() || self.max_retry_after.is_some() {
+            builder = builder.retry(self.retry_policy(None));
+        }
+        if let Some(path) = &self.cookies_file {
+            builder = builder.import_cookies(CookieSource::Netscape(path.to_path_buf()));
+        }
+        if let Some(source) = &self.cookies_from_browser {
+            builder = builder.import_cookies(source.clone());
+        }
+
+        for (domain, site) in self.sites.iter() {
+            if let Some(limit) = site.rate_limit {
+                builder = builder.rate_limit(domain, limit);
+            }
+            if let Some(retries) = site.retries {
+                builder = builder.site_retry(domain, self.retry_policy(Some(retries)));
+            }
+            for (name, value) in site.cookies.iter() {
+                builder = builder.cookie(domain, name, value);
+            }
+            if let (Some(username), Some(password)) = (&site.username, &site.password) {
+                builder = builder.credentials(
+                    domain,
+                    Credentials {
+                        username: username.to_string(),
+                        password: password.to_string(),
+                    },
+                );
+            }
+        }
+
+        builder
+    }
+
+    /// The retry policy of the config, `retries` replacing the number of retries it sets.
+    pub fn retry_policy(&self, retries: Option<u32>) -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+        if let Some(retries) = retries.or(self.retries) {
+            policy.max_attempts = retries + 1;
+        }
+        if let Some(seconds) = self.max_retry_after {
+            policy.max_retry_after = Duration::from_secs(seconds);
+        }
+
+        policy
+    }
+
+    /// The download options set by the config.
+    pub fn download_options(&self) -> DownloadOptions {
+        let mut options = DownloadOptions::default();
+
+        if let Some(output) = &self.output {
+            options.output = output.to_path_buf();
+        }
+        if let Some(template) = &self.template {
+            options.template = template.to_string();
+        }
+        if let Some(format) = self.format {
+            options.format = format;
+        }
+        if let Some(comic_info) = self.comic_info {
+            options.comic_info = comic_info;
+        }
+        if let Some(metadata) = self.metadata {
+            options.metadata = metadata;
+        }
+        if let Some(concurrency) = self.concurrency {
+            options.concurrency = concurrency;
+        }
+        if let Some(per_host) = self.per_host {
+            options.per_host = per_host;
+        }
+
+        options.site_format = self
+            .sites
+            .iter()
+            .filter_map(|(domain, site)| Some((domain.to_string(), site.format?)))
+            .collect();
+
+        options
+    }
+
+    /// The settings of the extractors set by the config.
+    pub fn extractor_options(&self) -> ExtractorOptions {
+        let original = |domain: &str| {
+            self.sites
+                .get(domain)
+                .and_then(|site| site.original)
+                .unwrap_or(false)
+        };
+
+        let mut options = ExtractorOptions::default();
+        options.ehentai.original = original("e-hentai.org");
+        options.exhentai.original = original("exhentai.org");
+
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignored_keys() {
+        let config = Config::parse(
+            r#"
+            concurency = 4
+            format = "cbz"
+
+            [sites."e-hentai.org"]
+            orignal = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.format, Some(Format::Cbz));
+        assert_eq!(config.concurrency, None);
+        assert_eq!(
+            config.ignored,
+            vec!["concurency", "sites.e-hentai.org.orignal"]
+        );
+    }
+
+    #[test]
+    fn site_format() {
+        let config = Config::parse(
+            r#"
+            format = "cbz"
+
+            [sites."e-hentai.org"]
+            format = "folder"
+            "#,
+        )
+        .unwrap();
+        let options = config.download_options();
+
+        let url = |url: &str| url.parse::<url::Url>().unwrap();
+        assert_eq!(
+            options.format_for(&url("https://e-hentai.org/g/1/a/")),
+            Format::Folder
+        );
+        assert_eq!(
+            options.format_for(&url("https://nhentai.net/g/1/")),
+            Format::Cbz
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use std::{
+    convert::TryFrom,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tempfile::TempDir;
+
+use crate::error::{Error, Result};
+
+/// A cookie read from a file or a browser.
+#[derive(Debug, Clone)]
+pub struct Cookie {
+    /// The domain without the leading dot.
+    pub domain: String,
+    /// Whether or not the cookie is also sent to the subdomains.
+    pub include_subdomains: bool,
+    pub path: String,
+    pub secure: bool,
+    /// In UNIX time, `None` for cookies lasting only until the browser is closed.
+    pub expires: Option<i64>,
+    pub name: String,
+    pub value: String,
+}
+
+impl Cookie {
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .map_or(false, |expires| expires < Utc::now().timestamp())
+    }
+
+    /// The cookie as a `Set-Cookie` header and the url that would have sent it.
+    pub(crate) fn to_set_cookie(&self) -> (String, String) {
+        let mut header = format!("{}={}; Path={}", self.name, self.value, self.path);
+        if self.include_subdomains {
+            header.push_str(&format!("; Domain={}", self.domain));
+        }
+        if self.secure {
+            header.push_str("; Secure");
+        }
+        if let Some(expires) = self.expires {
+            header.push_str(&format!("; Max-Age={}", expires - Utc::now().timestamp()));
+        }
+
+        let scheme = if self.secure { "https" } else { "http" };
+
+        (header, format!("{}://{}{}", scheme, self.domain, self.path))
+    }
+}
+
+/// Where cookies are imported from.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum CookieSource {
+    /// A `cookies.txt` in the Netscape format, the one exported by most browser extensions.
+    Netscape(PathBuf),
+    /// A Firefox profile directory, the default profile if none is given.
+    Firefox(Option<PathBuf>),
+    /// A Chromium or Chrome profile directory, the default profile of Chromium if none is given.
+    #[cfg(target_os = "linux")]
+    Chromium(Option<PathBuf>),
+}
+
+impl CookieSource {
+    pub fn load(&self) -> Result<Vec<Cookie>> {
+        match self {
+            CookieSource::Netscape(path) => Ok(parse_netscape(&fs::read_to_string(path)?)),
+            CookieSource::Firefox(profile) => match profile {
+                Some(profile) => read_firefox(profile),
+                None => read_firefox(&default_profile(&firefox_profiles(), "cookies.sqlite")?),
+            },
+            #[cfg(target_os = "linux")]
+            CookieSource::Chromium(profile) => match profile {
+                Some(profile) => read_chromium(profile),
+                None => read_chromium(&default_profile(&chromium_profiles(), "Cookies")?),
+            },
+        }
+    }
+}
+
+impl FromStr for CookieSource {
+    type Err = Error;
+
+    /// Parses a browser name optionally followed by the profile, e.g. `firefox:/path/to/profile`.
+    fn from_str(source: &str) -> Result<Self> {
+        let (browser, profile) = match source.split_once(':') {
+            Some((browser, profile)) => (browser, Some(PathBuf::from(profile))),
+            None => (source, None),
+        };
+
+        match browser {
+            "firefox" => Ok(CookieSource::Firefox(profile)),
+            #[cfg(target_os = "linux")]
+            "chromium" | "chrome" => Ok(CookieSource::Chromium(profile)),
+            _ => Err(Error::UnknownBrowser(browser.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for CookieSource {
+    type Error = Error;
+
+    fn try_from(source: String) -> Result<Self> {
+        source.parse()
+    }
+}
+
+/// Parses a `cookies.txt`, lines that are not cookies are skipped.
+pub fn parse_netscape(text: &str) -> Vec<Cookie> {
+    text.lines()
+        .filter_map(|line| {
+            // curl marks HttpOnly cookies with this prefix instead of commenting them out
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.starts_with('#') {
+                return None;
+            }
+
+            let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+            match fields[..] {
+                [domain, include_subdomains, path, secure, expires, name, value] => Some(Cookie {
+                    domain: domain.trim_start_matches('.').to_string(),
+                    include_subdomains: include_subdomains == "TRUE" || domain.starts_with('.'),
+                    path: path.to_string(),
+                    secure: secure == "TRUE",
+                    expires: expires.parse().ok().filter(|&expires| expires > 0),
+                    name: name.to_string(),
+                    value: value.to_string(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Reads the cookies of a Firefox profile.
+pub fn read_firefox(profile: &Path) -> Result<Vec<Cookie>> {
+    let copy = DatabaseCopy::open(&profile.join("cookies.sqlite"))?;
+
+    let mut statement = copy
+        .connection
+        .prepare("SELECT host, path, isSecure, expiry, name, value FROM moz_cookies")?;
+    let cookies = statement
+        .query_map(params![], |row| {
+            let host: String = row.get(0)?;
+            let expiry: i64 = row.get(3)?;
+
+            Ok(Cookie {
+                domain: host.trim_start_matches('.').to_string(),
+                include_subdomains: host.starts_with('.'),
+                path: row.get(1)?,
+                secure: row.get(2)?,
+                // Recent versions store milliseconds
+                expires: Some(if expiry > 100_000_000_000 {
+                    expiry / 1000
+                } else {
+                    expiry
+                }),
+                name: row.get(4)?,
+                value: row.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+
+    Ok(cookies)
+}
+
+/// Reads the cookies of a Chromium profile.
+///
+/// Values encrypted with a key kept in the desktop keyring can't be read and are left out.
+#[cfg(target_os = "linux")]
+pub fn read_chromium(profile: &Path) -> Result<Vec<Cookie>> {
+    // Newer versions moved the database to a subdirectory
+    let path = match profile.join("Network").join("Cookies") {
+        path if path.exists() => path,
+        _ => profile.join("Cookies"),
+    };
+    let copy = DatabaseCopy::open(&path)?;
+
+    // Since version 24 the value is prefixed with the hash of the domain
+    let version: i64 = copy
+        .connection
+        .query_row(
+            "SELECT value FROM meta WHERE key = 'version'",
+            params![],
+            |row| row.get::<_, String>(0),
+        )
+        .map(|version| version.parse().unwrap_or(0))
+        .unwrap_or(0);
+
+    let mut statement = copy.connection.prepare(
+        "SELECT host_key, path, is_secure, expires_utc, name, value, encrypted_value FROM cookies",
+    )?;
+    let rows = statement
+        .query_map(params![], |row| {
+            let host: String = row.get(0)?;
+            let expires: i64 = row.get(3)?;
+
+            Ok((
+                Cookie {
+                    domain: host.trim_start_matches('.').to_string(),
+                    include_subdomains: host.starts_with('.'),
+                    path: row.get(1)?,
+                    secure: row.get(2)?,
+                    // Microseconds since 1601
+                    expires: Some(expires / 1_000_000 - 11_644_473_600).filter(|_| expires > 0),
+                    name: row.get(4)?,
+                    value: row.get(5)?,
+                },
+                row.get::<_, Vec<u8>>(6)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(mut cookie, encrypted)| {
+            if !encrypted.is_empty() {
+                let mut value = chromium_decrypt(&encrypted)?;
+                if version >= 24 && value.len() >= 32 {
+                    value.drain(..32);
+                }
+                cookie.value = String::from_utf8(value).ok()?;
+            }
+
+            Some(cookie)
+        })
+        .collect())
+}
+
+/// Decrypts a value encrypted without the keyring, or with the keyring
+/// falling back to an empty password as it does when there is none.
+#[cfg(target_os = "linux")]
+fn chromium_decrypt(encrypted: &[u8]) -> Option<Vec<u8>> {
+    use openssl::{hash::MessageDigest, pkcs5::pbkdf2_hmac, symm};
+
+    let (password, data): (&[u8], _) = match encrypted.split_at(3.min(encrypted.len())) {
+        (b"v10", data) => (b"peanuts", data),
+        (b"v11", data) => (b"", data),
+        _ => return None,
+    };
+
+    let mut key = [0; 16];
+    pbkdf2_hmac(password, b"saltysalt", 1, MessageDigest::sha1(), &mut key).ok()?;
+
+    symm::decrypt(symm::Cipher::aes_128_cbc(), &key, Some(&[b' '; 16]), data).ok()
+}
+
+/// A copy of a browser database, the browser may be running and keep the original locked.
+///
+/// The copy holds every session cookie, so it is kept in a directory only the user can read.
+struct DatabaseCopy {
+    connection: Connection,
+    // Removed with everything inside once the connection is closed
+    _directory: TempDir,
+}
+
+impl DatabaseCopy {
+    fn open(path: &Path) -> Result<Self> {
+        let directory = tempfile::Builder::new().prefix("yuyu-cookies-").tempdir()?;
+        let copy = directory.path().join("cookies.sqlite");
+        copy_private(path, &copy)?;
+
+        // The latest changes may still be in the write-ahead log
+        if copy_private(&suffixed(path, "-wal"), &suffixed(&copy, "-wal")).is_err() {
+            fs::remove_file(suffixed(&copy, "-wal")).ok();
+        }
+
+        Ok(DatabaseCopy {
+            connection: Connection::open(&copy)?,
+            _directory: directory,
+        })
+    }
+}
+
+/// Copies a file to a new one that only the user can read, whatever the permissions of the original.
+fn copy_private(from: &Path, to: &Path) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut source = File::open(from)?;
+    io::copy(&mut source, &mut options.open(to)?)?;
+
+    Ok(())
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+
+    path.into()
+}
+
+fn firefox_profiles() -> Vec<PathBuf> {
+    dirs::home_dir()
+        .map(|home| vec![home.join(".mozilla").join("firefox")])
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "linux")]
+fn chromium_profiles() -> Vec<PathBuf> {
+    dirs::config_dir()
+        .map(|config| {
+            vec![
+                config.join("chromium").join("Default"),
+                config.join("google-chrome").join("Default"),
+            ]
+        })
+        .unwrap_or_default()
+}
+
+/// The most recently used profile holding `file`, looking in the given
+/// directories and the profiles directly inside them.
+fn default_profile(directories: &[PathBuf], file: &str) -> Result<PathBuf> {
+    directories
+        .iter()
+        .flat_map(|directory| {
+            let children = fs::read_dir(directory)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| Some(entry.ok()?.path()));
+
+            std::iter::once(directory.to_path_buf()).chain(children)
+        })
+        .filter_map(|profile| {
+            let modified = fs::metadata(profile.join(file))
+                .or_else(|_| fs::metadata(profile.join("Network").join(file)))
+                .ok()?
+                .modified()
+                .ok()?;
+
+            Some((modified, profile))
+        })
+        .max()
+        .map(|(_, profile)| profile)
+        .ok_or_else(|| Error::NotFound(format!("browser profile with {}", file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn netscape() {
+        let cookies = parse_netscape(
+            "# Netscape HTTP Cookie File\n\
+             \n\
+             .e-hentai.org\tTRUE\t/\tFALSE\t1893456000\tipb_member_id\t42\n\
+             #HttpOnly_forums.e-hentai.org\tFALSE\t/forums\tTRUE\t0\tpass_hash\tabc\r\n\
+             not a cookie\n",
+        );
+
+        assert_eq!(cookies.len(), 2);
+
+        assert_eq!(cookies[0].domain, "e-hentai.org");
+        assert!(cookies[0].include_subdomains);
+        assert_eq!(cookies[0].path, "/");
+        assert!(!cookies[0].secure);
+        assert_eq!(cookies[0].expires, Some(1_893_456_000));
+        assert_eq!(cookies[0].name, "ipb_member_id");
+        assert_eq!(cookies[0].value, "42");
+
+        assert_eq!(cookies[1].domain, "forums.e-hentai.org");
+        assert!(!cookies[1].include_subdomains);
+        assert_eq!(cookies[1].path, "/forums");
+        assert!(cookies[1].secure);
+        assert_eq!(cookies[1].expires, None);
+        assert_eq!(cookies[1].name, "pass_hash");
+        assert_eq!(cookies[1].value, "abc");
+    }
+
+    #[test]
+    fn leading_dot_includes_subdomains() {
+        let cookies = parse_netscape(".nhentai.net\tFALSE\t/\tTRUE\t0\tcsrftoken\tx");
+
+        assert_eq!(cookies[0].domain, "nhentai.net");
+        assert!(cookies[0].include_subdomains);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_copy() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let source = directory.path().join("cookies.sqlite");
+        fs::write(&source, "cookies").unwrap();
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let copy = directory.path().join("copy.sqlite");
+        copy_private(&source, &copy).unwrap();
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "cookies");
+        assert_eq!(
+            fs::metadata(&copy).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        // An existing file, or a link planted there, is never written through
+        assert!(copy_private(&source, &copy).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use image::imageops::FilterType;
+use rusqlite::{params, Connection};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::error::Result;
+
+/// The directory the store is kept in when none is given, inside the output directory.
+pub const DIRECTORY_NAME: &str = ".dedup";
+
+/// A content-addressed store of every page downloaded, so that the same image
+/// found in several comics is kept on disk only once.
+///
+/// Pages saved as files are hardlinked to the copy in the store, pages inside
+/// archives can't be and are only indexed, to find comics that overlap.
+pub struct Store {
+    root: PathBuf,
+    connection: Mutex<Connection>,
+    perceptual: bool,
+}
+
+/// Two comics sharing pages.
+#[derive(Debug, Clone)]
+pub struct Overlap {
+    /// The site and id of the comics.
+    pub first: (String, String),
+    pub second: (String, String),
+    /// The number of pages of the first comic also found in the second.
+    pub shared: usize,
+    /// The shared pages over the pages of the smaller comic, 1 means it is fully contained.
+    pub ratio: f64,
+}
+
+impl Store {
+    /// Opens the store kept in the directory, creating it if needed.
+    ///
+    /// If `perceptual` is true pages are also compared by a perceptual hash,
+    /// which matches the same image saved again with a different quality or format.
+    pub fn open<P: Into<PathBuf>>(root: P, perceptual: bool) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(root.join("objects"))?;
+
+        let connection = Connection::open(root.join("index.sqlite3"))?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS pages (
+                site TEXT NOT NULL,
+                id TEXT NOT NULL,
+                number INTEGER NOT NULL,
+                sha256 TEXT NOT NULL,
+                perceptual INTEGER,
+                path TEXT NOT NULL,
+                PRIMARY KEY (site, id, number)
+            );
+            CREATE INDEX IF NOT EXISTS pages_sha256 ON pages (sha256);
+            CREATE INDEX IF NOT EXISTS pages_perceptual ON pages (perceptual);",
+        )?;
+
+        Ok(Store {
+            root,
+            connection: Mutex::new(connection),
+            perceptual,
+        })
+    }
+
+    /// Adds a page saved as a file, replacing it with a hardlink to the stored
+    /// copy if the same page was already stored.
+    ///
+    /// Returns whether or not the page was a duplicate. When hardlinks are not
+    /// supported, e.g. across file systems, the file is kept as it is.
+    pub fn add_file(&self, site: &str, id: &str, number: usize, path: &Path) -> Result<bool> {
+        let contents = fs::read(path)?;
+        let sha256 = sha256(&contents);
+
+        let object = self.object_path(&sha256);
+        let duplicate = object.exists();
+
+        if duplicate {
+            // Linking to a temporary name first, so the page is never missing
+            let link = path.with_extension("link");
+            fs::remove_file(&link).ok();
+            if fs::hard_link(&object, &link).is_ok() {
+                fs::rename(&link, path)?;
+            }
+        } else {
+            if let Some(directory) = object.parent() {
+                fs::create_dir_all(directory)?;
+            }
+            fs::hard_link(path, &object).ok();
+        }
+
+        self.index(site, id, number, &sha256, &contents, path)?;
+
+        Ok(duplicate)
+    }
+
+    /// Adds a page saved inside the archive at `path`, only indexing it.
+    ///
+    /// Returns whether or not the page was a duplicate.
+    pub fn add_bytes(
+        &self,
+        site: &str,
+        id: &str,
+        number: usize,
+        contents: &[u8],
+        path: &Path,
+    ) -> Result<bool> {
+        let sha256 = sha256(contents);
+
+        let duplicate = self.connection.lock().unwrap().query_row(
+            "SELECT EXISTS (SELECT 1 FROM pages WHERE sha256 = ?1)",
+            params![sha256],
+            |row| row.get(0),
+        )?;
+
+        self.index(site, id, number, &sha256, contents, path)?;
+
+        Ok(duplicate)
+    }
+
+    fn index(
+        &self,
+        site: &str,
+        id: &str,
+        number: usize,
+        sha256: &str,
+        contents: &[u8],
+        path: &Path,
+    ) -> Result<()> {
+        let perceptual = if self.perceptual {
+            perceptual_hash(contents)
+        } else {
+            None
+        };
+
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO pages (site, id, number, sha256, perceptual, path)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                site,
+                id,
+                number as i64,
+                sha256,
+                perceptual,
+                path.to_string_lossy(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Every pair of comics sharing at least `min_ratio` of the pages of the smaller one,
+    /// the most overlapping first.
+    pub fn overlaps(&self, min_ratio: f64) -> Result<Vec<Overlap>> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut statement =
+            connection.prepare("SELECT site, id, COUNT(*) FROM pages GROUP BY site, id")?;
+        let totals = statement
+            .query_map(params![], |row| {
+                Ok(((row.get(0)?, row.get(1)?), row.get::<_, i64>(2)? as usize))
+            })?
+            .collect::<rusqlite::Result<HashMap<(String, String), usize>>>()?;
+
+        // Perceptual hashes are compared for equality, a distance would need every pair of pages
+        let mut statement = connection.prepare(
+            "SELECT a.site, a.id, b.site, b.id, COUNT(DISTINCT a.number)
+            FROM pages a JOIN pages b
+            ON (a.sha256 = b.sha256 OR a.perceptual = b.perceptual)
+            AND (a.site, a.id) < (b.site, b.id)
+            GROUP BY a.site, a.id, b.site, b.id",
+        )?;
+        let mut overlaps = statement
+            .query_map(params![], |row| {
+                Ok((
+                    (row.get(0)?, row.get(1)?),
+                    (row.get(2)?, row.get(3)?),
+                    row.get::<_, i64>(4)? as usize,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<((String, String), (String, String), usize)>>>()?
+            .into_iter()
+            .filter_map(|(first, second, shared)| {
+                let smaller = totals.get(&first)?.min(totals.get(&second)?);
+                let ratio = shared.min(*smaller) as f64 / *smaller as f64;
+
+                if ratio >= min_ratio {
+                    Some(Overlap {
+                        first,
+                        second,
+                        shared,
+                        ratio,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        overlaps.sort_by(|a, b| b.ratio.partial_cmp(&a.ratio).unwrap());
+
+        Ok(overlaps)
+    }
+
+    /// Where the copy of a page is stored, spread over directories to keep them small.
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        self.root
+            .join("objects")
+            .join(&sha256[..2])
+            .join(&sha256[2..])
+    }
+}
+
+impl fmt::Debug for Store {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Store")
+            .field("root", &self.root)
+            .field("perceptual", &self.perceptual)
+            .finish()
+    }
+}
+
+fn sha256(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// The difference hash of an image, each bit tells whether a pixel of
+/// the image shrunk to 9x8 is brighter than the one on its right.
+fn perceptual_hash(contents: &[u8]) -> Option<i64> {
+    let image = image::load_from_memory(contents).ok()?;
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    // SQLite integers are signed, only the bits matter
+    Some(hash as i64)
+}
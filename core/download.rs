@@ -0,0 +1,932 @@
+use bytes::Bytes;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use reqwest::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE},
+    Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::OsString,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+use url::Url;
+
+use crate::{
+    comic::{comicinfo, Page},
+    dedup::Store,
+    error::{Error, Result},
+    export::{self, Entry, Spawned},
+    extractor::{host_matches, Extractor, Media},
+    history::{History, Status},
+    metadata::{self, Metadata},
+    output::{self, DEFAULT_TEMPLATE},
+    progress::Progress,
+    session::Session,
+    Comic,
+};
+
+/// How a downloaded comic is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Every page in its own file, placed according to the template.
+    Folder,
+    /// A single zip archive with the cover first and then every page,
+    /// named after the directory the template would place the pages in.
+    Cbz,
+    /// A fixed-layout EPUB with one page per image, named like [`Format::Cbz`].
+    Epub,
+    /// A PDF with one page per image, named like [`Format::Cbz`].
+    Pdf,
+}
+
+impl Format {
+    /// The extension of the file the comic is stored in, if it is a single file.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Format::Folder => None,
+            Format::Cbz => Some("cbz"),
+            Format::Epub => Some("epub"),
+            Format::Pdf => Some("pdf"),
+        }
+    }
+}
+
+/// Options controlling how a comic is downloaded.
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// The format used for sites without their own in `site_format`.
+    pub format: Format,
+    /// The format used for comics of a domain and its subdomains.
+    pub site_format: Vec<(String, Format)>,
+    /// Whether or not to save a ComicInfo.xml with the metadata of the comic,
+    /// inside the archive or next to the pages.
+    pub comic_info: bool,
+    /// Whether or not to save a metadata.json next to each download, see [`Metadata`].
+    pub metadata: bool,
+    /// Maximum number of pages downloaded at the same time.
+    pub concurrency: usize,
+    /// Maximum number of pages downloaded at the same time from a single host.
+    pub per_host: usize,
+    /// The directory everything is downloaded into.
+    pub output: PathBuf,
+    /// Where each page goes inside the output directory, see [`output::render`].
+    pub template: String,
+    /// Comics already downloaded completely according to the history are skipped.
+    pub history: Option<Arc<History>>,
+    /// Downloads the comic again even if it was already, according to the history or the files.
+    pub force: bool,
+    /// Pages are added to the store, duplicates of pages already there are hardlinked.
+    pub dedup: Option<Arc<Store>>,
+}
+
+impl DownloadOptions {
+    /// The format comics extracted from the url are stored in.
+    pub fn format_for(&self, url: &Url) -> Format {
+        self.site_format
+            .iter()
+            .find(|(domain, _)| host_matches(url, domain))
+            .map_or(self.format, |(_, format)| *format)
+    }
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            format: Format::Folder,
+            site_format: vec![],
+            comic_info: true,
+            metadata: true,
+            concurrency: 8,
+            per_host: 4,
+            output: PathBuf::from("temp"),
+            template: DEFAULT_TEMPLATE.to_string(),
+            history: None,
+            force: false,
+            dedup: None,
+        }
+    }
+}
+
+/// How many times a page that failed is reloaded before giving up, see [`Extractor::reload`].
+const MAX_RELOADS: usize = 3;
+
+/// Downloads a comic extracted from `url` by `extractor`.
+pub async fn download_comic(
+    session: &Session,
+    extractor: &dyn Extractor,
+    url: &Url,
+    comic: &Comic,
+    options: &DownloadOptions,
+) -> Result<()> {
+    let options = &DownloadOptions {
+        format: options.format_for(url),
+        ..options.clone()
+    };
+
+    if is_downloaded(session, options, &comic.site, &comic.id).await? {
+        return Ok(());
+    }
+
+    if let Some(history) = &options.history {
+        let (history, comic, path) = (
+            history.clone(),
+            comic.clone(),
+            download_path(comic, options),
+        );
+        blocking(move || history.start(&comic, &path)).await?;
+    }
+
+    let result = match options.format.extension() {
+        None => download_folder(session, extractor, url, comic, options).await,
+        Some(extension) => download_file(session, extractor, url, comic, options, extension).await,
+    };
+
+    if let Some(history) = &options.history {
+        let status = match result {
+            Ok(_) => Status::Complete,
+            Err(_) => Status::Failed,
+        };
+        let (history, site, id) = (history.clone(), comic.site.clone(), comic.id.clone());
+        blocking(move || history.finish(&site, &id, status)).await?;
+    }
+
+    let metadata_path = result?;
+
+    if let (true, Some(path)) = (options.metadata, metadata_path) {
+        Metadata::new(url.clone(), Media::Comic(comic.clone()))
+            .save(path)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Whether or not the history has the comic as completely downloaded, in which
+/// case it is reported as skipped. Nothing is skipped when downloads are forced.
+pub(crate) async fn is_downloaded(
+    session: &Session,
+    options: &DownloadOptions,
+    site: &str,
+    id: &str,
+) -> Result<bool> {
+    let history = match (&options.history, options.force) {
+        (Some(history), false) => history.clone(),
+        _ => return Ok(false),
+    };
+
+    let (site, id) = (site.to_string(), id.to_string());
+    match blocking(move || history.get(&site, &id)).await? {
+        Some(entry) if entry.status == Status::Complete => {
+            session.report(Progress::Skipped {
+                title: entry.title,
+                path: entry.path,
+            });
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Returns where the metadata of the comic goes, if it has anywhere to go.
+async fn download_folder(
+    session: &Session,
+    extractor: &dyn Extractor,
+    url: &Url,
+    comic: &Comic,
+    options: &DownloadOptions,
+) -> Result<Option<PathBuf>> {
+    let paths: Vec<PathBuf> = output::render_pages(&options.template, comic)?
+        .into_iter()
+        .map(|path| options.output.join(path))
+        .collect();
+
+    let directories: BTreeSet<&Path> = paths.iter().filter_map(|path| path.parent()).collect();
+    for directory in directories {
+        fs::create_dir_all(directory).await?;
+    }
+
+    session.report(Progress::Comic {
+        title: comic.title.clone(),
+        pages: comic.pages.len(),
+    });
+
+    let fetcher = Fetcher::new(extractor, options.per_host);
+
+    // Only the first directory gets the metadata, templates rarely split pages
+    let directory = paths
+        .first()
+        .and_then(|path| path.parent())
+        .map(Path::to_path_buf);
+
+    stream::iter(comic.pages.iter().zip(paths).enumerate())
+        .map(|(index, (page, full_path))| {
+            download_page(
+                session,
+                comic,
+                options,
+                &fetcher,
+                index + 1,
+                page,
+                full_path,
+            )
+        })
+        .buffer_unordered(options.concurrency.max(1))
+        .try_collect::<()>()
+        .await?;
+
+    if let (true, Some(directory)) = (options.comic_info, &directory) {
+        let xml = comicinfo::comic_info(comic, url, false);
+        fs::write(directory.join(comicinfo::FILE_NAME), xml).await?;
+    }
+
+    Ok(directory.map(|directory| directory.join(metadata::FILE_NAME)))
+}
+
+/// Returns where the metadata of the comic goes, next to the file.
+async fn download_file(
+    session: &Session,
+    extractor: &dyn Extractor,
+    url: &Url,
+    comic: &Comic,
+    options: &DownloadOptions,
+    extension: &str,
+) -> Result<Option<PathBuf>> {
+    let base = base_path(comic, options);
+    let path = with_extension(&base, extension);
+    let metadata_path = with_extension(&base, metadata::FILE_NAME);
+
+    // Files are moved into place only once complete
+    if !options.force && fs::metadata(&path).await.is_ok() {
+        return Ok(Some(metadata_path));
+    }
+
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).await?;
+    }
+
+    session.report(Progress::Comic {
+        title: comic.title.clone(),
+        pages: comic.pages.len(),
+    });
+
+    let part_path = part_path(&path);
+    let writer = export::create(options.format, &part_path, comic, url, options.comic_info)?;
+    let writer = Spawned::new(writer, options.concurrency);
+
+    let written = write_pages(session, extractor, comic, options, &path, &writer).await;
+    // When the writer fails the pages can't be added anymore, so its error comes first
+    let result = writer.finish().await.and(written);
+
+    match result {
+        Ok(()) => fs::rename(&part_path, &path).await?,
+        Err(error) => {
+            fs::remove_file(&part_path).await.ok();
+            return Err(error);
+        }
+    }
+
+    Ok(Some(metadata_path))
+}
+
+async fn write_pages(
+    session: &Session,
+    extractor: &dyn Extractor,
+    comic: &Comic,
+    options: &DownloadOptions,
+    path: &Path,
+    writer: &Spawned,
+) -> Result<()> {
+    let fetcher = Fetcher::new(extractor, options.per_host);
+
+    let pages = std::iter::once(&comic.cover).chain(comic.pages.iter());
+
+    // Pages are downloaded concurrently but written in order
+    let mut downloads = stream::iter(pages.enumerate())
+        .map(|(number, page)| {
+            let fetcher = &fetcher;
+            async move {
+                let contents = fetcher
+                    .fetch(session, page, |url| async move {
+                        fetch_bytes(session, fetcher, &url).await
+                    })
+                    .await?;
+                Ok::<_, Error>(Entry {
+                    number,
+                    file_name: page.file_name.clone(),
+                    width: page.width,
+                    height: page.heigth,
+                    contents,
+                })
+            }
+        })
+        .buffered(options.concurrency.max(1));
+
+    while let Some(entry) = downloads.try_next().await? {
+        let number = entry.number;
+
+        // The cover is usually a page too
+        if let (Some(store), true) = (&options.dedup, number > 0) {
+            let (store, contents, path) = (store.clone(), entry.contents.clone(), path.to_owned());
+            let (site, id) = (comic.site.clone(), comic.id.clone());
+            blocking(move || store.add_bytes(&site, &id, number, &contents, &path)).await?;
+        }
+
+        writer.add(entry).await?;
+
+        if number > 0 {
+            session.report(Progress::Page {
+                number,
+                path: path.to_path_buf(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+async fn fetch_bytes(session: &Session, fetcher: &Fetcher<'_>, url: &Url) -> Result<Bytes> {
+    let _permit = fetcher.hosts.acquire(url).await;
+
+    session
+        .retry(url, || async move {
+            let mut response = check_image(session.send_once(session.get(url.to_owned())).await?)?;
+
+            let mut contents = vec![];
+            while let Some(chunk) = session.read(response.chunk()).await? {
+                contents.extend_from_slice(&chunk);
+            }
+
+            Ok(Bytes::from(contents))
+        })
+        .await
+}
+
+/// The directory or file a comic is saved to.
+fn download_path(comic: &Comic, options: &DownloadOptions) -> PathBuf {
+    match options.format.extension() {
+        None => {
+            let first = comic.pages.first().unwrap_or(&comic.cover);
+            let path = options
+                .output
+                .join(output::render(&options.template, comic, 1, first));
+
+            path.parent()
+                .map_or_else(|| options.output.clone(), Path::to_path_buf)
+        }
+        Some(extension) => with_extension(&base_path(comic, options), extension),
+    }
+}
+
+/// Where the file of a comic goes, without the extension: the directory the template would
+/// place the pages in, or `{site}/{id}` if the template places them in the output directory.
+fn base_path(comic: &Comic, options: &DownloadOptions) -> PathBuf {
+    let relative = output::render(&options.template, comic, 1, &comic.cover);
+
+    match relative.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => options.output.join(directory),
+        _ => options
+            .output
+            .join(output::sanitize(&comic.site))
+            .join(output::sanitize(&comic.id)),
+    }
+}
+
+async fn download_page(
+    session: &Session,
+    comic: &Comic,
+    options: &DownloadOptions,
+    fetcher: &Fetcher<'_>,
+    number: usize,
+    page: &Page,
+    full_path: PathBuf,
+) -> Result<()> {
+    let _permit = fetcher.hosts.acquire(&page.url).await;
+
+    // Pages are moved into place only once complete, so an existing file is
+    // only downloaded again if the server reports a different size.
+    let complete = match fs::metadata(&full_path).await {
+        Ok(_) if options.force => false,
+        Ok(metadata) => match remote_size(session, &page.url).await {
+            Some(size) => size == metadata.len(),
+            None => true,
+        },
+        Err(_) => false,
+    };
+
+    if !complete {
+        let full_path = &full_path;
+        fetcher
+            .fetch(session, page, |url| async move {
+                // Every attempt resumes from what the previous ones wrote
+                let result = session
+                    .retry(&url, || fetch_page(session, &url, full_path))
+                    .await;
+                if result.is_err() {
+                    // What was written can't be resumed from another url
+                    fs::remove_file(part_path(full_path)).await.ok();
+                    fs::remove_file(source_path(full_path)).await.ok();
+                }
+
+                result
+            })
+            .await?;
+    }
+
+    if let Some(store) = &options.dedup {
+        let (store, path) = (store.clone(), full_path.clone());
+        let (site, id) = (comic.site.clone(), comic.id.clone());
+        blocking(move || store.add_file(&site, &id, number, &path)).await?;
+    }
+
+    session.report(Progress::Page {
+        number,
+        path: full_path,
+    });
+
+    Ok(())
+}
+
+async fn fetch_page(session: &Session, url: &Url, full_path: &Path) -> Result<()> {
+    let part_path = part_path(full_path);
+    let source_path = source_path(full_path);
+    let mut resume = resumable(url, &part_path, &source_path).await;
+
+    let mut response = loop {
+        let mut request = session.get(url.to_owned());
+        if let Some((offset, source)) = &resume {
+            // The server sends the whole file instead if it changed since
+            request = request
+                .header(RANGE, format!("bytes={}-", offset))
+                .header(IF_RANGE, source.validator.as_str());
+        }
+
+        match session.send_once(request).await {
+            Ok(response) => break check_image(response)?,
+            Err(Error::Http(error))
+                if error.status() == Some(StatusCode::RANGE_NOT_SATISFIABLE)
+                    && resume.is_some() =>
+            {
+                // The partial file may already hold the whole page, otherwise it starts over
+                let offset = resume.take().map_or(0, |(offset, _)| offset);
+                if remote_size(session, url).await == Some(offset) {
+                    return complete_part(&part_path, &source_path, full_path).await;
+                }
+            }
+            Err(error) => return Err(error),
+        }
+    };
+
+    let resumed = response.status() == StatusCode::PARTIAL_CONTENT && resume.is_some();
+    let mut written = match (&resume, resumed) {
+        (Some((offset, _)), true) => *offset,
+        _ => 0,
+    };
+    let expected = response.content_length().map(|length| written + length);
+
+    if !resumed {
+        save_source(url, &response, &source_path).await?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&part_path)
+        .await?;
+
+    while let Some(chunk) = session.read(response.chunk()).await? {
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+    file.flush().await?;
+
+    if let Some(expected) = expected {
+        if written != expected {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} ended after {} of {} bytes", url, written, expected),
+            )));
+        }
+    }
+
+    complete_part(&part_path, &source_path, full_path).await
+}
+
+/// What a partial file was downloaded from, kept next to it so that it is
+/// only ever resumed with the bytes of the same file.
+#[derive(Serialize, Deserialize)]
+struct PartSource {
+    url: Url,
+    /// The `ETag` of the file, or its `Last-Modified` date if it has none.
+    validator: String,
+}
+
+/// How much of the page was already downloaded, if it can be resumed from `url`.
+async fn resumable(url: &Url, part_path: &Path, source_path: &Path) -> Option<(u64, PartSource)> {
+    let offset = fs::metadata(part_path).await.ok()?.len();
+    let source: PartSource = serde_json::from_slice(&fs::read(source_path).await.ok()?).ok()?;
+
+    if offset > 0 && source.url == *url {
+        Some((offset, source))
+    } else {
+        None
+    }
+}
+
+/// Remembers where a partial file comes from, a file without validator can't be resumed.
+async fn save_source(url: &Url, response: &Response, source_path: &Path) -> Result<()> {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    // Weak tags can't be used to resume
+    let validator = header(ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(LAST_MODIFIED));
+
+    match validator {
+        Some(validator) => {
+            let source = PartSource {
+                url: url.clone(),
+                validator: validator.to_string(),
+            };
+            fs::write(source_path, serde_json::to_vec(&source)?).await?;
+        }
+        None => {
+            fs::remove_file(source_path).await.ok();
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves a complete page into place.
+async fn complete_part(part_path: &Path, source_path: &Path, full_path: &Path) -> Result<()> {
+    fs::rename(part_path, full_path).await?;
+    fs::remove_file(source_path).await.ok();
+
+    Ok(())
+}
+
+/// Servers that refuse to serve a file, like when a quota is used up, often answer
+/// with a page explaining why instead.
+fn check_image(response: Response) -> Result<Response> {
+    let text = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("text/"));
+
+    if text {
+        Err(Error::NotAnImage(response.url().to_string()))
+    } else {
+        Ok(response)
+    }
+}
+
+/// Runs blocking work, like hashing or querying the database, on a thread where it can block.
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?
+}
+
+/// The size of the file as reported by the server, if it does.
+async fn remote_size(session: &Session, url: &Url) -> Option<u64> {
+    let response = session.send(session.head(url.to_owned())).await.ok()?;
+
+    response
+        .headers()
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// The path where a file is written while it is being downloaded.
+fn part_path(path: &Path) -> PathBuf {
+    with_extension(path, "part")
+}
+
+/// Where what a partial file is downloaded from is kept, see [`PartSource`].
+fn source_path(path: &Path) -> PathBuf {
+    with_extension(path, "part.source")
+}
+
+/// Appends an extension, unlike [`Path::with_extension`] which replaces the existing one.
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().map(OsString::from).unwrap_or_default();
+    file_name.push(".");
+    file_name.push(extension);
+
+    path.with_file_name(file_name)
+}
+
+/// What the downloads of the pages of a comic share.
+struct Fetcher<'a> {
+    extractor: &'a dyn Extractor,
+    hosts: HostLimits,
+}
+
+impl<'a> Fetcher<'a> {
+    fn new(extractor: &'a dyn Extractor, per_host: usize) -> Self {
+        Fetcher {
+            extractor,
+            hosts: HostLimits::new(per_host),
+        }
+    }
+
+    /// Runs `fetch` with the url of the page, then with its fallback and then
+    /// with the urls of the page reloaded by the extractor, until one succeeds.
+    async fn fetch<T, F, Fut>(&self, session: &Session, page: &Page, mut fetch: F) -> Result<T>
+    where
+        F: FnMut(Url) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut page = page.clone();
+        let mut reloads = 0;
+
+        loop {
+            let mut result = fetch(page.url.clone()).await;
+            if let (Err(_), Some(fallback)) = (&result, &page.fallback) {
+                result = fetch(fallback.clone()).await;
+            }
+
+            match result {
+                Err(error) if reloads < MAX_RELOADS => {
+                    match self.extractor.reload(session, &page).await? {
+                        Some(reloaded) => page = reloaded,
+                        None => return Err(error),
+                    }
+                    reloads += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Limits how many requests are sent at the same time to each host.
+struct HostLimits {
+    per_host: usize,
+    semaphores: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl HostLimits {
+    fn new(per_host: usize) -> Self {
+        HostLimits {
+            per_host: per_host.max(1),
+            semaphores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn acquire(&self, url: &Url) -> OwnedSemaphorePermit {
+        let semaphore = self
+            .semaphores
+            .lock()
+            .unwrap()
+            .entry(url.host_str().unwrap_or_default().to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_host)))
+            .clone();
+
+        // The semaphores are never closed
+        semaphore.acquire_owned().await.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    const PAGE: &[u8] = b"0123456789";
+    const ETAG_VALUE: &str = "\"v2\"";
+
+    /// Serves `PAGE` with its `ETag`, answering ranges only when `If-Range` matches.
+    async fn serve() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/1.jpg", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0];
+                    if socket.read(&mut byte).await.unwrap() == 0 {
+                        break;
+                    }
+                    request.push(byte[0]);
+                }
+                let request = String::from_utf8(request).unwrap().to_lowercase();
+                let header = |name: &str| {
+                    request
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(|value| value.trim().to_string())
+                };
+
+                let start = match (header("range: bytes="), header("if-range:")) {
+                    (Some(range), Some(tag)) if tag == ETAG_VALUE => {
+                        Some(range.trim_end_matches('-').parse::<usize>().unwrap())
+                    }
+                    _ => None,
+                };
+                let (status, body) = match start {
+                    Some(start) if start >= PAGE.len() => ("416 Range Not Satisfiable", &b""[..]),
+                    Some(start) => ("206 Partial Content", &PAGE[start..]),
+                    None => ("200 OK", PAGE),
+                };
+                let head = request.starts_with("head");
+
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\nETag: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    if head { PAGE.len() } else { body.len() },
+                    ETAG_VALUE
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                if !head {
+                    socket.write_all(body).await.unwrap();
+                }
+            }
+        });
+
+        url.parse().unwrap()
+    }
+
+    /// Leaves a partial page downloaded from `url` with the given validator.
+    async fn part(full_path: &Path, contents: &[u8], url: &Url, validator: &str) {
+        fs::write(part_path(full_path), contents).await.unwrap();
+        let source = PartSource {
+            url: url.clone(),
+            validator: validator.to_string(),
+        };
+        fs::write(source_path(full_path), serde_json::to_vec(&source).unwrap())
+            .await
+            .unwrap();
+    }
+
+    async fn fetch(url: &Url, full_path: &Path) -> Vec<u8> {
+        let session = Session::new().unwrap();
+        fetch_page(&session, url, full_path).await.unwrap();
+
+        assert!(fs::metadata(part_path(full_path)).await.is_err());
+        assert!(fs::metadata(source_path(full_path)).await.is_err());
+        fs::read(full_path).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn fetch_page_resumes_same_file() {
+        let (directory, url) = (tempfile::tempdir().unwrap(), serve().await);
+        let full_path = directory.path().join("001.jpg");
+
+        part(&full_path, b"01234", &url, ETAG_VALUE).await;
+        assert_eq!(fetch(&url, &full_path).await, PAGE);
+    }
+
+    #[tokio::test]
+    async fn fetch_page_restarts_other_file() {
+        let (directory, url) = (tempfile::tempdir().unwrap(), serve().await);
+        let full_path = directory.path().join("001.jpg");
+
+        // Written by another url, like the resampled version of the page
+        let other = url.join("fullimg.php").unwrap();
+        part(&full_path, b"abcde", &other, ETAG_VALUE).await;
+        assert_eq!(fetch(&url, &full_path).await, PAGE);
+
+        // The file changed on the server since
+        part(&full_path, b"abcde", &url, "\"v1\"").await;
+        assert_eq!(fetch(&url, &full_path).await, PAGE);
+    }
+
+    #[tokio::test]
+    async fn fetch_page_range_not_satisfiable() {
+        let (directory, url) = (tempfile::tempdir().unwrap(), serve().await);
+        let full_path = directory.path().join("001.jpg");
+
+        part(&full_path, PAGE, &url, ETAG_VALUE).await;
+        assert_eq!(fetch(&url, &full_path).await, PAGE);
+
+        // Longer than the page, it can't be the same file
+        part(&full_path, b"0123456789ab", &url, ETAG_VALUE).await;
+        assert_eq!(fetch(&url, &full_path).await, PAGE);
+    }
+
+    /// Reloads every page into a new url, counting how many times it did.
+    struct Reloader {
+        reloads: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Extractor for Reloader {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn matches(&self, _url: &Url) -> bool {
+            true
+        }
+
+        async fn extract(&self, _session: &Session, url: &Url) -> Result<Media> {
+            Err(Error::UnsupportedUrl(url.to_string()))
+        }
+
+        async fn reload(&self, _session: &Session, page: &Page) -> Result<Option<Page>> {
+            let reloads = self.reloads.fetch_add(1, Ordering::SeqCst) + 1;
+            let mut page = page.clone();
+            page.url = format!("https://example.com/{}.jpg", reloads).parse()?;
+
+            Ok(Some(page))
+        }
+    }
+
+    fn page() -> Page {
+        Page {
+            fallback: Some("https://example.com/fallback.jpg".parse().unwrap()),
+            file_name: "1.jpg".to_string(),
+            heigth: None,
+            reload: None,
+            url: "https://example.com/0.jpg".parse().unwrap(),
+            width: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn fetcher_gives_up_after_reloads() {
+        let session = Session::new().unwrap();
+        let extractor = Reloader {
+            reloads: AtomicUsize::new(0),
+        };
+        let fetcher = Fetcher::new(&extractor, 1);
+        let tried = Mutex::new(Vec::new());
+
+        let result: Result<()> = fetcher
+            .fetch(&session, &page(), |url| {
+                tried.lock().unwrap().push(url.to_string());
+                async move { Err(Error::NotFound(url.to_string())) }
+            })
+            .await;
+
+        // The error of the last url tried
+        assert!(
+            matches!(result, Err(Error::NotFound(url)) if url == "https://example.com/fallback.jpg")
+        );
+        assert_eq!(extractor.reloads.load(Ordering::SeqCst), MAX_RELOADS);
+        // The fallback is kept by the reloaded pages
+        assert_eq!(
+            *tried.lock().unwrap(),
+            vec![
+                "https://example.com/0.jpg",
+                "https://example.com/fallback.jpg",
+                "https://example.com/1.jpg",
+                "https://example.com/fallback.jpg",
+                "https://example.com/2.jpg",
+                "https://example.com/fallback.jpg",
+                "https://example.com/3.jpg",
+                "https://example.com/fallback.jpg",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fetcher_uses_reloaded_page() {
+        let session = Session::new().unwrap();
+        let extractor = Reloader {
+            reloads: AtomicUsize::new(0),
+        };
+        let fetcher = Fetcher::new(&extractor, 1);
+
+        let fetched = fetcher
+            .fetch(&session, &page(), |url| async move {
+                match url.path() {
+                    "/2.jpg" => Ok(url),
+                    _ => Err(Error::NotFound(url.to_string())),
+                }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(fetched.as_str(), "https://example.com/2.jpg");
+        assert_eq!(extractor.reloads.load(Ordering::SeqCst), 2);
+    }
+}
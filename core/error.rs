@@ -0,0 +1,151 @@
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use std::time::Duration;
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong while extracting or downloading.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// No extractor is able to handle the url.
+    #[error("unsupported url: {0}")]
+    UnsupportedUrl(String),
+    /// The page does not look like what the extractor expects,
+    /// this usually means the site changed its layout.
+    #[error("the layout of {site} changed, could not find {what}")]
+    LayoutChanged {
+        site: &'static str,
+        what: &'static str,
+    },
+    /// The requested resource does not exist (anymore).
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// The resource is only available to logged in users.
+    #[error("{site} requires a logged in session")]
+    LoginRequired { site: &'static str },
+    /// Cookies can't be imported from the browser.
+    #[error("unknown browser: {0}")]
+    UnknownBrowser(String),
+    /// A header given to the session is not a valid HTTP header.
+    #[error("invalid header: {0}")]
+    InvalidHeader(String),
+    /// A metadata file written by a newer version, whose schema is not known.
+    #[error("unsupported metadata version {0}")]
+    UnsupportedVersion(u32),
+    /// The output template gives several pages of a comic the same path.
+    #[error("the template places several pages at {0}")]
+    PathCollision(String),
+    /// The server answered with a page where an image was expected.
+    #[error("{0} is not an image")]
+    NotAnImage(String),
+    /// The site refused the request because too many were sent.
+    #[error("rate limited by {host}")]
+    RateLimited {
+        host: String,
+        /// How long the site asked to wait before retrying, if it did.
+        retry_after: Option<Duration>,
+    },
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+    #[error(transparent)]
+    Url(#[from] url::ParseError),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+}
+
+impl Error {
+    /// Whether or not the same request could succeed if tried again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::RateLimited { .. } => true,
+            Error::Http(error) => {
+                error.is_timeout()
+                    || error.is_connect()
+                    || error.is_request()
+                    || error.is_body()
+                    || error.status().map_or(false, |s| s.is_server_error())
+            }
+            // A connection closed or stalled before the whole body was received
+            Error::Io(error) => matches!(
+                error.kind(),
+                std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Turns error status codes into the matching [`Error`].
+pub(crate) fn check_status(response: Response) -> Result<Response> {
+    match response.status() {
+        StatusCode::NOT_FOUND => Err(Error::NotFound(response.url().to_string())),
+        status @ StatusCode::TOO_MANY_REQUESTS | status @ StatusCode::SERVICE_UNAVAILABLE => {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
+
+            // Without a delay to wait for, an unavailable server is just failing
+            if status == StatusCode::SERVICE_UNAVAILABLE && retry_after.is_none() {
+                return Ok(response.error_for_status()?);
+            }
+
+            Err(Error::RateLimited {
+                host: response.url().host_str().unwrap_or_default().to_string(),
+                retry_after,
+            })
+        }
+        _ => Ok(response.error_for_status()?),
+    }
+}
+
+/// Parses a `Retry-After` header, either a number of seconds or an HTTP-date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    // A date in the past means the request can be sent right away
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn retry_after_date() {
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::from_secs(0))
+        );
+
+        let date = (chrono::Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+        let delay = parse_retry_after(&date).unwrap();
+        assert!(delay > Duration::from_secs(50) && delay <= Duration::from_secs(60));
+    }
+}
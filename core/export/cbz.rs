@@ -0,0 +1,60 @@
+use reqwest::Url;
+use std::{fs::File, io::Write, path::Path};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    comic::comicinfo,
+    error::Result,
+    export::{entry_name, Entry, Writer},
+    Comic,
+};
+
+/// A zip archive with the cover first and then every page.
+pub struct Cbz {
+    zip: ZipWriter<File>,
+    comic_info: Option<String>,
+    total: usize,
+}
+
+impl Cbz {
+    /// If `comic_info` is true a ComicInfo.xml pointing to `url` is added after the pages.
+    pub fn create(path: &Path, comic: &Comic, url: &Url, comic_info: bool) -> Result<Self> {
+        Ok(Cbz {
+            zip: ZipWriter::new(File::create(path)?),
+            comic_info: if comic_info {
+                Some(comicinfo::comic_info(comic, url, true))
+            } else {
+                None
+            },
+            total: comic.pages.len(),
+        })
+    }
+}
+
+impl Writer for Cbz {
+    fn add(&mut self, entry: Entry) -> Result<()> {
+        // Images are already compressed, compressing them again only wastes time
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+
+        self.zip.start_file(
+            entry_name(entry.number, self.total, &entry.file_name),
+            options,
+        )?;
+        self.zip.write_all(&entry.contents)?;
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        if let Some(xml) = &self.comic_info {
+            let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+            self.zip.start_file(comicinfo::FILE_NAME, options)?;
+            self.zip.write_all(xml.as_bytes())?;
+        }
+
+        self.zip.finish()?.sync_all()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+use chrono::Utc;
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{Cursor, Write},
+    path::Path,
+};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    comic::comicinfo::{escape, language_code},
+    error::Result,
+    export::{entry_name, Entry, Writer},
+    Comic,
+};
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml" />
+  </rootfiles>
+</container>
+"#;
+
+/// A fixed-layout EPUB 3 with one image per page.
+///
+/// The cover is only the cover image of the book, it is either a page already or a thumbnail.
+pub struct Epub {
+    zip: ZipWriter<File>,
+    metadata: String,
+    title: String,
+    total: usize,
+    /// The image and page of every entry added so far.
+    items: Vec<Item>,
+}
+
+struct Item {
+    image: String,
+    media_type: &'static str,
+    /// `None` for the cover, which has no page.
+    page: Option<String>,
+}
+
+impl Epub {
+    pub fn create(path: &Path, comic: &Comic) -> Result<Self> {
+        let mut zip = ZipWriter::new(File::create(path)?);
+
+        // Readers identify the format by the first entry, which can't be compressed
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(b"application/epub+zip")?;
+
+        zip.start_file("META-INF/container.xml", deflated())?;
+        zip.write_all(CONTAINER.as_bytes())?;
+
+        Ok(Epub {
+            zip,
+            metadata: metadata(comic),
+            title: comic.title.clone(),
+            total: comic.pages.len(),
+            items: vec![],
+        })
+    }
+}
+
+impl Writer for Epub {
+    fn add(&mut self, entry: Entry) -> Result<()> {
+        let (width, height) = match (entry.width, entry.height) {
+            (Some(width), Some(height)) => (width, height),
+            // Not every site tells the size of its pages
+            _ => image::io::Reader::new(Cursor::new(&entry.contents[..]))
+                .with_guessed_format()?
+                .into_dimensions()?,
+        };
+
+        let name = entry_name(entry.number, self.total, &entry.file_name);
+        let item = Item {
+            image: format!("images/{}", name),
+            media_type: media_type(&entry.file_name),
+            page: match entry.number {
+                0 => None,
+                number => Some(format!(
+                    "pages/{}.xhtml",
+                    entry_name(number, self.total, "")
+                )),
+            },
+        };
+
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        self.zip
+            .start_file(format!("OEBPS/{}", item.image), stored)?;
+        self.zip.write_all(&entry.contents)?;
+
+        if let Some(page_path) = &item.page {
+            self.zip
+                .start_file(format!("OEBPS/{}", page_path), deflated())?;
+            self.zip
+                .write_all(page(&self.title, &item.image, width, height).as_bytes())?;
+        }
+
+        self.items.push(item);
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.zip.start_file("OEBPS/nav.xhtml", deflated())?;
+        self.zip
+            .write_all(navigation(&self.title, &self.items).as_bytes())?;
+
+        self.zip.start_file("OEBPS/content.opf", deflated())?;
+        self.zip
+            .write_all(package(&self.metadata, &self.items).as_bytes())?;
+
+        self.zip.finish()?.sync_all()?;
+
+        Ok(())
+    }
+}
+
+fn deflated() -> FileOptions {
+    FileOptions::default().compression_method(CompressionMethod::Deflated)
+}
+
+fn media_type(file_name: &str) -> &'static str {
+    let extension = Path::new(file_name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+/// The page showing a single image, sized to the image so that it fills the screen.
+fn page(title: &str, image: &str, width: u32, height: u32) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>{title}</title>
+  <meta name="viewport" content="width={width}, height={height}" />
+  <style>html, body {{ margin: 0; padding: 0; width: {width}px; height: {height}px; }} img {{ display: block; width: {width}px; height: {height}px; }}</style>
+</head>
+<body>
+  <img src="../{image}" alt="" />
+</body>
+</html>
+"#,
+        title = escape(title),
+        width = width,
+        height = height,
+        image = image,
+    )
+}
+
+/// EPUB 3 requires a table of contents, it only points to the first page.
+fn navigation(title: &str, items: &[Item]) -> String {
+    let mut xhtml = String::new();
+
+    xhtml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n");
+    xhtml.push_str("<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n");
+    writeln!(xhtml, "<head><title>{}</title></head>", escape(title)).unwrap();
+    xhtml.push_str("<body>\n  <nav epub:type=\"toc\">\n    <ol>\n");
+    if let Some(first) = items.iter().find_map(|item| item.page.as_ref()) {
+        writeln!(
+            xhtml,
+            "      <li><a href=\"{}\">{}</a></li>",
+            first,
+            escape(title)
+        )
+        .unwrap();
+    }
+    xhtml.push_str("    </ol>\n  </nav>\n</body>\n</html>\n");
+
+    xhtml
+}
+
+/// The Dublin Core metadata of the package.
+fn metadata(comic: &Comic) -> String {
+    let mut xml = String::new();
+
+    // Writing to a String never fails
+    writeln!(
+        xml,
+        "    <dc:identifier id=\"id\">urn:yuyu:{}:{}</dc:identifier>",
+        escape(&comic.site),
+        escape(&comic.id)
+    )
+    .unwrap();
+    writeln!(xml, "    <dc:title>{}</dc:title>", escape(&comic.title)).unwrap();
+
+    for author in comic.authors.iter() {
+        writeln!(xml, "    <dc:creator>{}</dc:creator>", escape(author)).unwrap();
+    }
+
+    let languages: Vec<&str> = comic
+        .languages
+        .iter()
+        .filter_map(|language| language_code(language))
+        .collect();
+    if languages.is_empty() {
+        // At least one language is required, this one means undetermined
+        xml.push_str("    <dc:language>und</dc:language>\n");
+    }
+    for language in languages {
+        writeln!(xml, "    <dc:language>{}</dc:language>", language).unwrap();
+    }
+
+    for tag in comic.tags.iter() {
+        writeln!(
+            xml,
+            "    <dc:subject>{}</dc:subject>",
+            escape(&tag.to_string())
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        xml,
+        "    <meta property=\"dcterms:modified\">{}</meta>",
+        Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    )
+    .unwrap();
+    xml.push_str("    <meta property=\"rendition:layout\">pre-paginated</meta>\n");
+    xml.push_str("    <meta property=\"rendition:spread\">none</meta>\n");
+
+    xml
+}
+
+fn package(metadata: &str, items: &[Item]) -> String {
+    let mut xml = String::new();
+
+    xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"id\" prefix=\"rendition: http://www.idpf.org/vocab/rendition/#\">\n");
+    xml.push_str("  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n");
+    xml.push_str(metadata);
+    xml.push_str("  </metadata>\n");
+
+    xml.push_str("  <manifest>\n");
+    xml.push_str("    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\" />\n");
+    for (index, item) in items.iter().enumerate() {
+        let properties = match item.page {
+            None => " properties=\"cover-image\"",
+            Some(_) => "",
+        };
+
+        writeln!(
+            xml,
+            "    <item id=\"image{0}\" href=\"{1}\" media-type=\"{2}\"{3} />",
+            index, item.image, item.media_type, properties
+        )
+        .unwrap();
+        if let Some(page) = &item.page {
+            writeln!(
+                xml,
+                "    <item id=\"page{0}\" href=\"{1}\" media-type=\"application/xhtml+xml\" />",
+                index, page
+            )
+            .unwrap();
+        }
+    }
+    xml.push_str("  </manifest>\n");
+
+    xml.push_str("  <spine>\n");
+    for (index, _) in items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| item.page.is_some())
+    {
+        writeln!(xml, "    <itemref idref=\"page{}\" />", index).unwrap();
+    }
+    xml.push_str("  </spine>\n");
+
+    xml.push_str("</package>\n");
+
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(number: usize) -> Item {
+        Item {
+            image: format!("images/{:03}.jpg", number),
+            media_type: "image/jpeg",
+            page: match number {
+                0 => None,
+                number => Some(format!("pages/{:03}.xhtml", number)),
+            },
+        }
+    }
+
+    #[test]
+    fn cover_only_in_manifest() {
+        let items: Vec<Item> = (0..3).map(item).collect();
+        let xml = package("", &items);
+
+        assert!(xml.contains(
+            r#"<item id="image0" href="images/000.jpg" media-type="image/jpeg" properties="cover-image" />"#
+        ));
+        assert!(!xml.contains(r#"id="page0""#));
+        assert!(xml.contains(
+            "  <spine>\n    <itemref idref=\"page1\" />\n    <itemref idref=\"page2\" />\n  </spine>"
+        ));
+
+        assert!(navigation("title", &items).contains(r#"<a href="pages/001.xhtml">"#));
+    }
+}
@@ -0,0 +1,112 @@
+use bytes::Bytes;
+use reqwest::Url;
+use std::{io, path::Path};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+pub mod cbz;
+pub mod epub;
+pub mod pdf;
+
+use crate::{download::Format, error::Result, Comic};
+
+/// A page ready to be written, the cover is number 0.
+pub struct Entry {
+    pub number: usize,
+    pub file_name: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub contents: Bytes,
+}
+
+/// A file format comics are written into, one page at a time and in order.
+pub trait Writer: Send {
+    fn add(&mut self, entry: Entry) -> Result<()>;
+
+    /// Writes what is left once every page is added.
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Creates the writer of a format stored as a single file.
+pub(crate) fn create(
+    format: Format,
+    path: &Path,
+    comic: &Comic,
+    url: &Url,
+    comic_info: bool,
+) -> Result<Box<dyn Writer>> {
+    match format {
+        Format::Cbz => Ok(Box::new(cbz::Cbz::create(path, comic, url, comic_info)?)),
+        Format::Epub => Ok(Box::new(epub::Epub::create(path, comic)?)),
+        Format::Pdf => Ok(Box::new(pdf::Pdf::create(path, comic)?)),
+        Format::Folder => unreachable!("folders are not a single file"),
+    }
+}
+
+/// Runs a [`Writer`] on a blocking thread, so that pages can be added
+/// while the following ones are still being downloaded.
+pub(crate) struct Spawned {
+    sender: mpsc::Sender<Entry>,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl Spawned {
+    /// At most `buffer` entries are kept in memory waiting to be written.
+    pub(crate) fn new(mut writer: Box<dyn Writer>, buffer: usize) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<Entry>(buffer.max(1));
+
+        let handle = tokio::task::spawn_blocking(move || {
+            while let Some(entry) = receiver.blocking_recv() {
+                writer.add(entry)?;
+            }
+
+            writer.finish()
+        });
+
+        Spawned { sender, handle }
+    }
+
+    /// Adds an entry after the ones already added.
+    pub(crate) async fn add(&self, entry: Entry) -> Result<()> {
+        self.sender
+            .send(entry)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the writer stopped").into())
+    }
+
+    /// Waits for every entry to be written, returning the error that stopped the writer if any.
+    pub(crate) async fn finish(self) -> Result<()> {
+        drop(self.sender);
+
+        self.handle
+            .await
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?
+    }
+}
+
+/// The name of a page inside an archive, zero-padded so that readers sort them right.
+pub(crate) fn entry_name(number: usize, total: usize, file_name: &str) -> String {
+    let width = total.to_string().len().max(3);
+
+    match Path::new(file_name).extension() {
+        Some(extension) => format!(
+            "{:0width$}.{}",
+            number,
+            extension.to_string_lossy(),
+            width = width
+        ),
+        None => format!("{:0width$}", number, width = width),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_names() {
+        assert_eq!(entry_name(0, 20, "cover.jpg"), "000.jpg");
+        assert_eq!(entry_name(7, 999, "7.png"), "007.png");
+        assert_eq!(entry_name(42, 1200, "42.webp"), "0042.webp");
+        assert_eq!(entry_name(3, 5, "page"), "003");
+    }
+}
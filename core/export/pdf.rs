@@ -0,0 +1,321 @@
+use flate2::{write::ZlibEncoder, Compression};
+use image::{DynamicImage, GenericImageView};
+use jpeg_decoder::PixelFormat;
+use std::{
+    fs::File,
+    io::{BufWriter, Cursor, Write},
+    path::Path,
+};
+
+use crate::{
+    error::Result,
+    export::{Entry, Writer},
+    Comic,
+};
+
+// Objects written once every page is, their numbers are reserved upfront
+const CATALOG: usize = 1;
+const PAGES: usize = 2;
+const INFO: usize = 3;
+
+/// A PDF with one page per image, each page as big as its image.
+/// The cover is left out, it is either a page already or a thumbnail.
+///
+/// JPEGs are embedded as they are, other formats are decoded and compressed losslessly.
+pub struct Pdf {
+    file: BufWriter<File>,
+    /// Bytes written so far.
+    position: u64,
+    /// Where every object starts, the first being number 1.
+    offsets: Vec<u64>,
+    info: String,
+    pages: Vec<usize>,
+}
+
+/// An image as a PDF expects it.
+struct Image {
+    width: u32,
+    height: u32,
+    color_space: &'static str,
+    filter: &'static str,
+    /// Extra entries of the image dictionary.
+    extra: &'static str,
+    data: Vec<u8>,
+    /// The opacity of every pixel, if the image is not opaque.
+    alpha: Option<Vec<u8>>,
+}
+
+impl Pdf {
+    pub fn create(path: &Path, comic: &Comic) -> Result<Self> {
+        let mut pdf = Pdf {
+            file: BufWriter::new(File::create(path)?),
+            position: 0,
+            offsets: vec![0; INFO],
+            info: format!(
+                "<< /Title {} /Author {} /Keywords {} /Creator {} >>",
+                text(&comic.title),
+                text(&comic.authors.join(", ")),
+                text(
+                    &comic
+                        .tags
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                text(crate::session::USER_AGENT)
+            ),
+            pages: vec![],
+        };
+
+        // The comment with binary characters tells tools the file is not plain text
+        pdf.write(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n")?;
+
+        Ok(pdf)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.file.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+
+        Ok(())
+    }
+
+    /// Reserves the number of an object written later.
+    fn reserve(&mut self) -> usize {
+        self.offsets.push(0);
+        self.offsets.len()
+    }
+
+    fn object(&mut self, number: usize, dictionary: &str, stream: Option<&[u8]>) -> Result<()> {
+        self.offsets[number - 1] = self.position;
+
+        self.write(format!("{} 0 obj\n{}\n", number, dictionary).as_bytes())?;
+        if let Some(stream) = stream {
+            self.write(b"stream\n")?;
+            self.write(stream)?;
+            self.write(b"\nendstream\n")?;
+        }
+        self.write(b"endobj\n")
+    }
+
+    fn image(&mut self, image: Image) -> Result<usize> {
+        let mask = match &image.alpha {
+            Some(alpha) => {
+                let number = self.reserve();
+                let dictionary = format!(
+                    "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceGray /BitsPerComponent 8 /Filter /FlateDecode /Length {} >>",
+                    image.width,
+                    image.height,
+                    alpha.len()
+                );
+                self.object(number, &dictionary, Some(alpha))?;
+
+                format!(" /SMask {} 0 R", number)
+            }
+            None => String::new(),
+        };
+
+        let number = self.reserve();
+        let dictionary = format!(
+            "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace {} /BitsPerComponent 8 /Filter {}{}{} /Length {} >>",
+            image.width,
+            image.height,
+            image.color_space,
+            image.filter,
+            image.extra,
+            mask,
+            image.data.len()
+        );
+        self.object(number, &dictionary, Some(&image.data))?;
+
+        Ok(number)
+    }
+}
+
+impl Writer for Pdf {
+    fn add(&mut self, entry: Entry) -> Result<()> {
+        if entry.number == 0 {
+            return Ok(());
+        }
+
+        let image = match jpeg(&entry.contents) {
+            Some(image) => image,
+            None => decoded(image::load_from_memory(&entry.contents)?)?,
+        };
+        let (width, height) = (image.width, image.height);
+
+        let image = self.image(image)?;
+
+        // One unit is one pixel, the image is scaled from 1x1 to the whole page
+        let content = format!("q {} 0 0 {} 0 0 cm /Im0 Do Q", width, height);
+        let number = self.reserve();
+        self.object(
+            number,
+            &format!("<< /Length {} >>", content.len()),
+            Some(content.as_bytes()),
+        )?;
+
+        let page = self.reserve();
+        let dictionary = format!(
+            "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << /XObject << /Im0 {} 0 R >> >> /Contents {} 0 R >>",
+            PAGES, width, height, image, number
+        );
+        self.object(page, &dictionary, None)?;
+
+        self.pages.push(page);
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let kids: Vec<String> = self
+            .pages
+            .iter()
+            .map(|page| format!("{} 0 R", page))
+            .collect();
+        let pages = format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            kids.len()
+        );
+        self.object(PAGES, &pages, None)?;
+
+        let catalog = format!("<< /Type /Catalog /Pages {} 0 R >>", PAGES);
+        self.object(CATALOG, &catalog, None)?;
+
+        let info = std::mem::take(&mut self.info);
+        self.object(INFO, &info, None)?;
+
+        let start = self.position;
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in self.offsets.iter() {
+            xref.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        xref.push_str(&format!(
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            CATALOG,
+            INFO,
+            start
+        ));
+        self.write(xref.as_bytes())?;
+
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+
+        Ok(())
+    }
+}
+
+/// A JPEG as it is, PDF readers decode it themselves.
+fn jpeg(contents: &[u8]) -> Option<Image> {
+    let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(contents));
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+
+    let (color_space, extra) = match info.pixel_format {
+        PixelFormat::L8 => ("/DeviceGray", ""),
+        PixelFormat::RGB24 => ("/DeviceRGB", ""),
+        // Almost every CMYK JPEG is saved by Photoshop, which inverts the values
+        PixelFormat::CMYK32 => ("/DeviceCMYK", " /Decode [1 0 1 0 1 0 1 0]"),
+    };
+
+    Some(Image {
+        width: info.width.into(),
+        height: info.height.into(),
+        color_space,
+        filter: "/DCTDecode",
+        extra,
+        data: contents.to_vec(),
+        alpha: None,
+    })
+}
+
+fn decoded(image: DynamicImage) -> Result<Image> {
+    let (width, height) = image.dimensions();
+
+    let alpha = if image.color().has_alpha() {
+        let alpha: Vec<u8> = image.to_rgba8().pixels().map(|pixel| pixel[3]).collect();
+        Some(compress(&alpha)?)
+    } else {
+        None
+    };
+
+    Ok(Image {
+        width,
+        height,
+        color_space: "/DeviceRGB",
+        filter: "/FlateDecode",
+        extra: "",
+        data: compress(&image.to_rgb8())?,
+        alpha,
+    })
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+
+    Ok(encoder.finish()?)
+}
+
+/// A text string, encoded as UTF-16 so that any character can be used.
+fn text(text: &str) -> String {
+    let mut encoded = String::from("<FEFF");
+    for unit in text.encode_utf16() {
+        encoded.push_str(&format!("{:04X}", unit));
+    }
+    encoded.push('>');
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use image::{ImageOutputFormat, RgbImage};
+
+    fn entry(number: usize) -> Entry {
+        let mut contents = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(4, 3))
+            .write_to(&mut contents, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+
+        Entry {
+            number,
+            file_name: format!("{}.jpg", number),
+            width: None,
+            height: None,
+            contents: Bytes::from(contents),
+        }
+    }
+
+    #[test]
+    fn pages_without_cover() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("comic.pdf");
+        let comic: Comic = serde_json::from_str(
+            r#"{
+                "authors": [], "categories": [], "characters": [], "groups": [],
+                "cover": { "file_name": "cover.jpg", "url": "https://example.com/cover.jpg" },
+                "id": "1", "languages": [], "pages": [], "site": "nhentai", "tags": [],
+                "title": "title", "translated": false, "upload_date": 0.0
+            }"#,
+        )
+        .unwrap();
+
+        let mut pdf = Box::new(Pdf::create(&path, &comic).unwrap());
+        for number in 0..3 {
+            pdf.add(entry(number)).unwrap();
+        }
+        pdf.finish().unwrap();
+
+        let pdf = std::fs::read(&path).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert_eq!(pdf.matches("/Type /Page ").count(), 2);
+        assert!(pdf.contains("/Count 2"));
+        assert!(pdf.contains("/MediaBox [0 0 4 3]"));
+    }
+}
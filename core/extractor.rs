@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    comic::{
+        ehentai::{self, EHentai, ExHentai},
+        nhentai::NHentai,
+        pixiv::Pixiv,
+        Page,
+    },
+    error::Result,
+    session::Session,
+    yt::{YouTube, YtInitialPlayerResponse},
+    Comic, Foundry, Image,
+};
+
+/// Everything an [`Extractor`] can return.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Media {
+    Comic(Comic),
+    Image(Image),
+    Video(YtInitialPlayerResponse),
+}
+
+/// A site module able to extract [`Media`] from its own urls.
+#[async_trait]
+pub trait Extractor: Send + Sync {
+    /// The name of the site, this is the same value used in [`Comic::site`].
+    fn name(&self) -> &'static str;
+
+    /// Whether or not the url belongs to this site.
+    fn matches(&self, url: &Url) -> bool;
+
+    async fn extract(&self, session: &Session, url: &Url) -> Result<Media>;
+
+    /// The site and id of the comic the url points to, as in [`Comic::site`]
+    /// and [`Comic::id`], for sites where the url is enough to tell them.
+    fn comic_id(&self, _url: &Url) -> Option<(&'static str, String)> {
+        None
+    }
+
+    /// Another version of a page whose download failed, for sites that have a way
+    /// to get one, e.g. from another server.
+    async fn reload(&self, _session: &Session, _page: &Page) -> Result<Option<Page>> {
+        Ok(None)
+    }
+}
+
+/// A list of extractors to which urls get dispatched.
+pub struct Registry {
+    extractors: Vec<Box<dyn Extractor>>,
+}
+
+impl Registry {
+    /// Creates a registry without any extractor.
+    pub fn new() -> Self {
+        Registry { extractors: vec![] }
+    }
+
+    /// Adds an extractor, extractors are tried in the order they are registered.
+    pub fn register<E: Extractor + 'static>(&mut self, extractor: E) -> &mut Self {
+        self.extractors.push(Box::new(extractor));
+        self
+    }
+
+    /// Returns the first extractor matching the url.
+    pub fn find(&self, url: &Url) -> Option<&dyn Extractor> {
+        self.extractors
+            .iter()
+            .find(|extractor| extractor.matches(url))
+            .map(|extractor| extractor.as_ref())
+    }
+}
+
+/// The settings of the extractors which have some.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractorOptions {
+    pub ehentai: ehentai::Options,
+    pub exhentai: ehentai::Options,
+}
+
+impl Registry {
+    /// Creates a registry with every supported site, configured by `options`.
+    pub fn with_options(options: &ExtractorOptions) -> Self {
+        let mut registry = Registry::new();
+
+        registry
+            .register(EHentai {
+                options: options.ehentai,
+            })
+            .register(ExHentai {
+                options: options.exhentai,
+            })
+            .register(Foundry)
+            .register(NHentai)
+            .register(Pixiv)
+            .register(YouTube);
+
+        registry
+    }
+}
+
+impl Default for Registry {
+    /// Creates a registry with every supported site.
+    fn default() -> Self {
+        Registry::with_options(&ExtractorOptions::default())
+    }
+}
+
+/// Whether or not the host of the url is `domain` or one of its subdomains.
+pub(crate) fn host_matches(url: &Url, domain: &str) -> bool {
+    match url.host_str() {
+        Some(host) => host == domain || host.ends_with(&format!(".{}", domain)),
+        None => false,
+    }
+}
@@ -0,0 +1,154 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{error::Result, Comic};
+
+/// The file the history is kept in when none is given, inside the output directory.
+pub const FILE_NAME: &str = "history.sqlite3";
+
+/// How far a download went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Started and never finished, the program was probably stopped.
+    Downloading,
+    Complete,
+    Failed,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Downloading => "downloading",
+            Status::Complete => "complete",
+            Status::Failed => "failed",
+        }
+    }
+
+    fn parse(status: &str) -> Self {
+        match status {
+            "complete" => Status::Complete,
+            "failed" => Status::Failed,
+            _ => Status::Downloading,
+        }
+    }
+}
+
+/// A comic in the history.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub site: String,
+    pub id: String,
+    pub title: String,
+    pub pages: usize,
+    /// The directory or file the comic was saved to.
+    pub path: PathBuf,
+    pub status: Status,
+    /// When the download started or finished, in RFC 3339.
+    pub updated_at: String,
+}
+
+/// Every comic downloaded so far, so that the same one is not downloaded twice.
+///
+/// Comics are identified by their site and id, the same comic pasted with a
+/// different url is still found.
+pub struct History {
+    connection: Mutex<Connection>,
+}
+
+impl History {
+    /// Opens the history kept in the file, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if let Some(directory) = path.as_ref().parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS downloads (
+                site TEXT NOT NULL,
+                id TEXT NOT NULL,
+                title TEXT NOT NULL,
+                pages INTEGER NOT NULL,
+                path TEXT NOT NULL,
+                status TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (site, id)
+            )",
+        )?;
+
+        Ok(History {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    pub fn get(&self, site: &str, id: &str) -> Result<Option<Entry>> {
+        let connection = self.connection.lock().unwrap();
+
+        let entry = connection
+            .query_row(
+                "SELECT site, id, title, pages, path, status, updated_at
+                FROM downloads WHERE site = ?1 AND id = ?2",
+                params![site, id],
+                |row| {
+                    Ok(Entry {
+                        site: row.get(0)?,
+                        id: row.get(1)?,
+                        title: row.get(2)?,
+                        pages: row.get::<_, i64>(3)? as usize,
+                        path: PathBuf::from(row.get::<_, String>(4)?),
+                        status: Status::parse(&row.get::<_, String>(5)?),
+                        updated_at: row.get(6)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(entry)
+    }
+
+    /// Whether or not the comic was already downloaded completely.
+    pub fn is_complete(&self, site: &str, id: &str) -> Result<bool> {
+        Ok(self
+            .get(site, id)?
+            .map_or(false, |entry| entry.status == Status::Complete))
+    }
+
+    /// Records the comic as being downloaded to `path`, replacing what was there.
+    pub fn start(&self, comic: &Comic, path: &Path) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO downloads (site, id, title, pages, path, status, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                comic.site,
+                comic.id,
+                comic.title,
+                comic.pages.len() as i64,
+                path.to_string_lossy(),
+                Status::Downloading.as_str(),
+                Utc::now().to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn finish(&self, site: &str, id: &str, status: Status) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "UPDATE downloads SET status = ?1, updated_at = ?2 WHERE site = ?3 AND id = ?4",
+            params![status.as_str(), Utc::now().to_rfc3339(), site, id],
+        )?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("History").finish()
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use crate::error::Result;
 
 pub async fn download() -> Result<()> {
     Ok(())
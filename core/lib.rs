@@ -1,55 +1,100 @@
-use anyhow::Result;
+use async_trait::async_trait;
 use nipper::Document;
-use regex::Regex;
-use reqwest::ClientBuilder;
-use serde::{Deserialize, Serialize};
-use std::path::Path;
-use tokio::fs;
 use url::Url;
 
 pub mod comic;
+pub mod config;
+pub mod cookies;
+pub mod dedup;
+pub mod download;
+pub mod error;
+pub mod export;
+pub mod extractor;
+pub mod history;
 pub mod images;
+pub mod metadata;
+pub mod output;
+pub mod progress;
+pub mod ratelimit;
+pub mod retry;
+pub mod session;
 pub mod yt;
 
-pub use comic::{ehentai, nhentai, pixiv, Comic, Image};
+pub use comic::{ehentai, nhentai, pixiv, Comic, Image, Tag};
+pub use config::Config;
+pub use download::{download_comic, DownloadOptions, Format};
+pub use error::{Error, Result};
+pub use extractor::{Extractor, ExtractorOptions, Media, Registry};
+pub use history::History;
 pub use images::instagram;
+pub use metadata::Metadata;
+pub use progress::{Progress, Reporter};
+pub use ratelimit::RateLimit;
+pub use retry::RetryPolicy;
+pub use session::{Credentials, Session, SessionBuilder};
 
-pub async fn start(url: &str) -> Result<()> {
-    yt::main(url).await?;
+use extractor::host_matches;
 
-    // instagram::download().await?;
+pub async fn start(
+    session: &Session,
+    registry: &Registry,
+    url: &str,
+    options: &DownloadOptions,
+) -> Result<()> {
+    let url: Url = url.parse()?;
 
-    Ok(())
-}
+    let extractor = registry
+        .find(&url)
+        .ok_or_else(|| Error::UnsupportedUrl(url.to_string()))?;
 
-pub async fn foundry(url: &str) -> Result<Image> {
-    let client = ClientBuilder::new().cookie_store(true).build()?;
+    // Comics already downloaded are skipped before asking their site for anything
+    if let Some((site, id)) = extractor.comic_id(&url) {
+        if download::is_downloaded(session, options, site, &id).await? {
+            return Ok(());
+        }
+    }
 
-    let resp = client.get(url).send().await?.text().await?;
-    fs::write("temp/foundry.html", &resp).await?;
+    match extractor.extract(session, &url).await? {
+        Media::Comic(comic) => download_comic(session, extractor, &url, &comic, options).await?,
+        Media::Image(image) => println!("Image url: {}", image.url),
+        Media::Video(video) => yt::print_formats(&video),
+    }
 
-    let document = Document::from(&resp);
-    let img = document.select("img.center").attr("src").unwrap();
-    let img: Url = img.replacen("//", "https://", 1).parse()?;
+    // instagram::download().await?;
 
-    Ok(Image { url: img })
+    Ok(())
 }
 
-pub async fn download_comic(comic: &Comic) -> Result<()> {
-    for page in comic.pages.iter() {
-        let path = format!("temp/{}/{}", comic.site, comic.id);
-        println!("{}", &path);
+pub struct Foundry;
 
-        fs::create_dir_all(&path).await?;
-
-        let full_path = format!("{}/{}", &path, page.file_name);
-        let full_path = Path::new(&full_path);
+#[async_trait]
+impl Extractor for Foundry {
+    fn name(&self) -> &'static str {
+        "hentai-foundry"
+    }
 
-        println!("{:#?}", full_path);
+    fn matches(&self, url: &Url) -> bool {
+        host_matches(url, "hentai-foundry.com") && url.path().starts_with("/pictures/")
+    }
 
-        let image = reqwest::get(page.url.to_owned()).await?.bytes().await?;
-        fs::write(full_path, image).await?;
+    async fn extract(&self, session: &Session, url: &Url) -> Result<Media> {
+        Ok(Media::Image(foundry(session, url.as_str()).await?))
     }
+}
 
-    Ok(())
+pub async fn foundry(session: &Session, url: &str) -> Result<Image> {
+    let resp = session.get_text(url).await?;
+    session.dump("hentai-foundry", "page.html", &resp).await;
+
+    let document = Document::from(&resp);
+    let img = document
+        .select("img.center")
+        .attr("src")
+        .ok_or(Error::LayoutChanged {
+            site: "hentai-foundry",
+            what: "img.center",
+        })?;
+    let img: Url = img.replacen("//", "https://", 1).parse()?;
+
+    Ok(Image { url: img })
 }
@@ -0,0 +1,172 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::Path;
+use tokio::fs;
+use url::Url;
+
+use crate::{
+    error::{Error, Result},
+    extractor::Media,
+    Comic,
+};
+
+/// The version of the schema written, bumped whenever a field is renamed,
+/// removed or changes meaning. Adding fields keeps the version.
+///
+/// 2: the tags of comics have a namespace.
+pub const VERSION: u32 = 2;
+
+/// The name of the file saved next to every download.
+pub const FILE_NAME: &str = "metadata.json";
+
+/// What is saved next to a download, so that scripts can index it
+/// and the comic can be loaded back without asking the site again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Metadata {
+    pub version: u32,
+    /// The url the media was extracted from.
+    pub url: Url,
+    pub downloaded_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub media: Media,
+}
+
+/// Just enough to know whether or not the rest can be read.
+#[derive(Deserialize)]
+struct Versioned {
+    version: u32,
+}
+
+impl Metadata {
+    /// The metadata of media downloaded now.
+    pub fn new(url: Url, media: Media) -> Self {
+        Metadata {
+            version: VERSION,
+            url,
+            downloaded_at: Utc::now(),
+            media,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Parses metadata written by this or an older version, which is migrated to this one.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let Versioned { version } = serde_json::from_str(json)?;
+        if version > VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let mut value: Value = serde_json::from_str(json)?;
+        if version < 2 {
+            migrate_v1(&mut value);
+        }
+
+        let mut metadata: Metadata = serde_json::from_value(value)?;
+        metadata.version = VERSION;
+
+        Ok(metadata)
+    }
+
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Metadata::from_json(&fs::read_to_string(path).await?)
+    }
+
+    pub async fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_json()?).await?;
+
+        Ok(())
+    }
+
+    /// Loads the comic saved in a metadata file.
+    pub async fn load_comic<P: AsRef<Path>>(path: P) -> Result<Comic> {
+        match Metadata::load(&path).await?.media {
+            Media::Comic(comic) => Ok(comic),
+            _ => Err(Error::NotFound(format!(
+                "comic in {}",
+                path.as_ref().display()
+            ))),
+        }
+    }
+}
+
+/// Version 1 saved the tags of comics as plain names, their namespace already dropped.
+fn migrate_v1(value: &mut Value) {
+    if value["type"] != "comic" {
+        return;
+    }
+
+    if let Some(tags) = value
+        .pointer_mut("/data/tags")
+        .and_then(Value::as_array_mut)
+    {
+        for tag in tags.iter_mut() {
+            if let Some(name) = tag.as_str().map(str::to_string) {
+                *tag = json!({ "namespace": null, "name": name });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comic::Tag;
+
+    const V1: &str = r#"{
+        "version": 1,
+        "url": "https://nhentai.net/g/1/",
+        "downloaded_at": "2021-04-01T00:00:00Z",
+        "type": "comic",
+        "data": {
+            "authors": [],
+            "categories": [],
+            "characters": [],
+            "cover": { "file_name": "cover.jpg", "url": "https://t.nhentai.net/cover.jpg" },
+            "id": "1",
+            "groups": [],
+            "languages": ["english"],
+            "pages": [],
+            "site": "nhentai",
+            "tags": ["glasses", "full color"],
+            "title": "title",
+            "translated": false,
+            "upload_date": 0.0
+        }
+    }"#;
+
+    #[test]
+    fn migrate_v1_tags() {
+        let metadata = Metadata::from_json(V1).unwrap();
+        assert_eq!(metadata.version, VERSION);
+
+        match metadata.media {
+            Media::Comic(comic) => assert_eq!(
+                comic.tags,
+                vec![Tag::new(None, "glasses"), Tag::new(None, "full color")]
+            ),
+            media => panic!("expected a comic, got {:?}", media),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let metadata = Metadata::from_json(V1).unwrap();
+        let json = metadata.to_json().unwrap();
+
+        assert_eq!(Metadata::from_json(&json).unwrap().to_json().unwrap(), json);
+    }
+
+    #[test]
+    fn reject_newer_version() {
+        let json = V1.replace(r#""version": 1"#, &format!(r#""version": {}"#, VERSION + 1));
+
+        assert!(matches!(
+            Metadata::from_json(&json),
+            Err(Error::UnsupportedVersion(version)) if version == VERSION + 1
+        ));
+    }
+}
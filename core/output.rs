@@ -0,0 +1,273 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    comic::{Comic, Page},
+    error::{Error, Result},
+};
+
+/// The template used when none is given, it keeps every comic in its own directory.
+pub const DEFAULT_TEMPLATE: &str = "{site}/{id}/{page:03}.{ext}";
+
+/// Renders the path of a page relative to the output directory.
+///
+/// The template is a path where `/` separates directories and the following
+/// fields are replaced, any other text is kept as is:
+///
+/// - `{site}`, `{id}` and `{title}` of the comic
+/// - `{authors}`, `{groups}` and `{languages}` joined by a comma
+/// - `{page}` the number of the page starting from 1, `{page:03}` pads it with zeros
+/// - `{ext}` the extension of the page and `{file_name}` the name it has on the site
+///
+/// The fields are sanitized so that they are valid file names on both Linux and Windows.
+pub fn render(template: &str, comic: &Comic, number: usize, page: &Page) -> PathBuf {
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let field = &rest[1..end];
+        let (name, format) = match field.split_once(':') {
+            Some((name, format)) => (name, Some(format)),
+            None => (field, None),
+        };
+
+        match value(name, format, comic, number, page) {
+            Some(value) if value.is_empty() => (),
+            Some(value) => rendered.push_str(&sanitize(&value)),
+            None => rendered.push_str(&rest[..=end]),
+        }
+
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+
+    rendered
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .collect()
+}
+
+/// Renders the path of every page of the comic, which must all be different:
+/// a template without `{page}` or `{file_name}` usually places them all in one file.
+pub fn render_pages(template: &str, comic: &Comic) -> Result<Vec<PathBuf>> {
+    let mut rendered = HashSet::new();
+
+    comic
+        .pages
+        .iter()
+        .enumerate()
+        .map(|(index, page)| {
+            let path = render(template, comic, index + 1, page);
+            if !rendered.insert(path.clone()) {
+                return Err(Error::PathCollision(path.display().to_string()));
+            }
+
+            Ok(path)
+        })
+        .collect()
+}
+
+fn value(
+    name: &str,
+    format: Option<&str>,
+    comic: &Comic,
+    number: usize,
+    page: &Page,
+) -> Option<String> {
+    let value = match name {
+        "site" => comic.site.clone(),
+        "id" => comic.id.clone(),
+        "title" => comic.title.clone(),
+        "authors" => comic.authors.join(", "),
+        "groups" => comic.groups.join(", "),
+        "languages" => comic.languages.join(", "),
+        "page" => {
+            let width = format.and_then(|f| f.parse().ok()).unwrap_or(0);
+
+            match format {
+                Some(format) if format.starts_with('0') => {
+                    format!("{:0width$}", number, width = width)
+                }
+                _ => format!("{:width$}", number, width = width),
+            }
+        }
+        "ext" => Path::new(&page.file_name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        "file_name" => page.file_name.clone(),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+/// Makes the text a valid file name on both Linux and Windows.
+pub fn sanitize(name: &str) -> String {
+    // Windows refuses these names whatever the extension is
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    // Most file systems limit names to 255 bytes, the rest is left for extensions
+    const MAX_LENGTH: usize = 200;
+
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    if sanitized.len() > MAX_LENGTH {
+        let mut end = MAX_LENGTH;
+        while !sanitized.is_char_boundary(end) {
+            end -= 1;
+        }
+        sanitized.truncate(end);
+    }
+
+    // Windows drops trailing dots and spaces, this also gets rid of `.` and `..`
+    let sanitized = sanitized
+        .trim_end_matches(|c| c == '.' || c == ' ')
+        .trim_start();
+
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    if RESERVED
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        format!("_{}", sanitized)
+    } else if sanitized.is_empty() {
+        "_".to_string()
+    } else {
+        sanitized.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+
+    fn page(file_name: &str) -> Page {
+        Page {
+            fallback: None,
+            file_name: file_name.to_string(),
+            heigth: None,
+            reload: None,
+            url: Url::parse("https://example.com/1.jpg").unwrap(),
+            width: None,
+        }
+    }
+
+    fn comic(title: &str) -> Comic {
+        Comic {
+            authors: vec!["a".to_string(), "b".to_string()],
+            categories: Vec::new(),
+            characters: Vec::new(),
+            cover: page("cover.jpg"),
+            id: "123".to_string(),
+            groups: Vec::new(),
+            languages: vec!["english".to_string()],
+            pages: vec![page("1.jpg")],
+            site: "nhentai".to_string(),
+            tags: Vec::new(),
+            title: title.to_string(),
+            translated: false,
+            upload_date: 0.0,
+        }
+    }
+
+    #[test]
+    fn render_default_template() {
+        let path = render(DEFAULT_TEMPLATE, &comic("title"), 7, &page("7.png"));
+        assert_eq!(path, PathBuf::from("nhentai/123/007.png"));
+    }
+
+    #[test]
+    fn render_fields() {
+        let path = render(
+            "{authors} - {title}/{page}_{file_name}",
+            &comic("a/b"),
+            12,
+            &page("x.jpg"),
+        );
+        assert_eq!(path, PathBuf::from("a, b - a_b/12_x.jpg"));
+    }
+
+    #[test]
+    fn render_keeps_unknown_fields() {
+        let path = render(
+            "{unknown}/{groups}/{page",
+            &comic("title"),
+            1,
+            &page("1.jpg"),
+        );
+        assert_eq!(path, PathBuf::from("{unknown}/{page"));
+    }
+
+    #[test]
+    fn render_pages_paths() {
+        let mut comic = comic("title");
+        comic.pages.push(page("2.png"));
+
+        assert_eq!(
+            render_pages(DEFAULT_TEMPLATE, &comic).unwrap(),
+            vec![
+                PathBuf::from("nhentai/123/001.jpg"),
+                PathBuf::from("nhentai/123/002.png")
+            ]
+        );
+        assert!(render_pages("{title}/{file_name}", &comic).is_ok());
+    }
+
+    #[test]
+    fn render_pages_collision() {
+        let mut comic = comic("title");
+        comic.pages.push(page("2.jpg"));
+
+        assert!(matches!(
+            render_pages("{authors}/{title}.{ext}", &comic),
+            Err(Error::PathCollision(path)) if path == "a, b/title.jpg"
+        ));
+    }
+
+    #[test]
+    fn sanitize_forbidden_characters() {
+        assert_eq!(sanitize("a<b>:c\"d|e?f*g\\h\n"), "a_b__c_d_e_f_g_h_");
+    }
+
+    #[test]
+    fn sanitize_reserved_names() {
+        assert_eq!(sanitize("con"), "_con");
+        assert_eq!(sanitize("LPT1.txt"), "_LPT1.txt");
+        assert_eq!(sanitize("CONSOLE"), "CONSOLE");
+    }
+
+    #[test]
+    fn sanitize_dots_and_spaces() {
+        assert_eq!(sanitize(".."), "_");
+        assert_eq!(sanitize("  name. . "), "name");
+        assert_eq!(sanitize(""), "_");
+    }
+
+    #[test]
+    fn sanitize_truncates_on_char_boundary() {
+        let sanitized = sanitize(&"あ".repeat(100));
+        assert!(sanitized.len() <= 200);
+        assert_eq!(sanitized, "あ".repeat(66));
+    }
+}
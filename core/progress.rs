@@ -0,0 +1,24 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use url::Url;
+
+/// Events sent while extracting and downloading.
+#[derive(Debug, Clone)]
+pub enum Progress {
+    /// A comic started downloading.
+    Comic { title: String, pages: usize },
+    /// A comic was not downloaded because it already was, according to the history.
+    Skipped { title: String, path: PathBuf },
+    /// A page is on disk, either because it was downloaded or because it already was.
+    Page { number: usize, path: PathBuf },
+    /// A request failed and is going to be retried.
+    Retry {
+        url: Url,
+        /// The attempt that is going to be made, the first retry is attempt 2.
+        attempt: u32,
+        delay: Duration,
+        error: String,
+    },
+}
+
+/// A callback receiving [`Progress`] events.
+pub type Reporter = Arc<dyn Fn(Progress) + Send + Sync>;
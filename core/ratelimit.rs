@@ -0,0 +1,176 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+use crate::{comic::ehentai, comic::pixiv, extractor::host_matches};
+
+/// The maximum rate at which requests are sent to a host.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RateLimit {
+    /// Requests per second allowed on average.
+    pub per_second: f64,
+    /// Requests that can be sent at once before being slowed down.
+    pub burst: u32,
+}
+
+/// The limits every site ships with, overridable with [`SessionBuilder::rate_limit`].
+///
+/// [`SessionBuilder::rate_limit`]: crate::SessionBuilder::rate_limit
+pub fn defaults() -> Vec<(String, RateLimit)> {
+    ehentai::RATE_LIMITS
+        .iter()
+        .chain(pixiv::RATE_LIMITS.iter())
+        .map(|(domain, limit)| (domain.to_string(), *limit))
+        .collect()
+}
+
+/// A token bucket for each host with a [`RateLimit`].
+pub(crate) struct RateLimiter {
+    limits: Vec<(String, RateLimit)>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// The first limit whose domain matches a host is the one used.
+    pub(crate) fn new(limits: Vec<(String, RateLimit)>) -> Self {
+        RateLimiter {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until a request can be sent to the host of the url.
+    pub(crate) async fn acquire(&self, url: &Url) {
+        let (host, limit) = match (url.host_str(), self.limit(url)) {
+            (Some(host), Some(limit)) => (host, limit),
+            _ => return,
+        };
+
+        let burst = limit.burst.max(1) as f64;
+
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let bucket = buckets.entry(host.to_string()).or_insert_with(|| Bucket {
+                    tokens: burst,
+                    updated: Instant::now(),
+                });
+
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * limit.per_second).min(burst);
+                bucket.updated = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / limit.per_second)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    pub(crate) fn limit(&self, url: &Url) -> Option<RateLimit> {
+        self.limits
+            .iter()
+            .find(|(domain, _)| host_matches(url, domain))
+            .map(|(_, limit)| *limit)
+            .filter(|limit| limit.per_second > 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> (RateLimiter, Url) {
+        let limit = RateLimit {
+            per_second: 20.0,
+            burst: 3,
+        };
+
+        (
+            RateLimiter::new(vec![("example.com".to_string(), limit)]),
+            "https://example.com/".parse().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn burst() {
+        let (limiter, url) = limiter();
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire(&url).await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(25));
+
+        // The fourth one waits for a token, 1/20 of a second
+        limiter.acquire(&url).await;
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
+
+    #[tokio::test]
+    async fn refill() {
+        let (limiter, url) = limiter();
+        for _ in 0..3 {
+            limiter.acquire(&url).await;
+        }
+
+        // Enough time for two tokens, not more than the burst is ever kept
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        let start = Instant::now();
+        for _ in 0..2 {
+            limiter.acquire(&url).await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(25));
+
+        limiter.acquire(&url).await;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn unlimited_hosts() {
+        let (limiter, _) = limiter();
+        let url: Url = "https://nhentai.net/".parse().unwrap();
+
+        let start = Instant::now();
+        for _ in 0..100 {
+            limiter.acquire(&url).await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(25));
+    }
+
+    #[test]
+    fn first_matching_limit() {
+        let limit = |per_second| RateLimit {
+            per_second,
+            burst: 1,
+        };
+        let limiter = RateLimiter::new(vec![
+            ("e-hentai.org".to_string(), limit(1.0)),
+            ("e-hentai.org".to_string(), limit(4.0)),
+            ("nhentai.net".to_string(), limit(0.0)),
+        ]);
+
+        let url = |url: &str| url.parse::<Url>().unwrap();
+        assert_eq!(
+            limiter.limit(&url("https://api.e-hentai.org/api.php")),
+            Some(limit(1.0))
+        );
+        // A limit of zero means unlimited
+        assert_eq!(limiter.limit(&url("https://nhentai.net/")), None);
+    }
+}
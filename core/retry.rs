@@ -0,0 +1,80 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// How failed requests are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, the first one included.
+    pub max_attempts: u32,
+    /// Delay before the first retry, it doubles at every following one.
+    pub base_delay: Duration,
+    /// Upper bound for the delay between two attempts.
+    pub max_delay: Duration,
+    /// Upper bound for the delay a site asks for with `Retry-After`, which is
+    /// usually longer than the ones of the policy.
+    pub max_retry_after: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// The delay before the given attempt, with some jitter so that
+    /// concurrent requests don't all retry at the same time.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(2).min(31);
+        let delay = self
+            .base_delay
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        let half = delay.as_millis() as u64 / 2;
+        Duration::from_millis(half + rand::thread_rng().gen_range(0..=half))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_retry_after: Duration::from_secs(300),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_bounds() {
+        let policy = RetryPolicy::default();
+
+        for _ in 0..100 {
+            // The first retry waits between half and all of the base delay
+            let delay = policy.delay(2);
+            assert!(delay >= policy.base_delay / 2 && delay <= policy.base_delay);
+
+            let delay = policy.delay(4);
+            assert!(delay >= policy.base_delay * 2 && delay <= policy.base_delay * 4);
+        }
+    }
+
+    #[test]
+    fn delay_capped() {
+        let policy = RetryPolicy::default();
+
+        for attempt in [10, 40, u32::MAX].iter() {
+            let delay = policy.delay(*attempt);
+            assert!(delay >= policy.max_delay / 2 && delay <= policy.max_delay);
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use chrono::Utc;
+use reqwest::{
+    cookie::Jar,
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Client, IntoUrl, Proxy, Request, RequestBuilder, Response,
+};
+use std::{future::Future, io, path::PathBuf, sync::Arc, time::Duration};
+use tokio::fs;
+use url::Url;
+
+use crate::{
+    comic::pixiv,
+    cookies::{Cookie, CookieSource},
+    error::{check_status, Error, Result},
+    extractor::host_matches,
+    progress::{Progress, Reporter},
+    ratelimit::{self, RateLimit, RateLimiter},
+    retry::RetryPolicy,
+};
+
+/// The default user agent, sites usually block requests without one.
+pub const USER_AGENT: &str = concat!("yuyu/", env!("CARGO_PKG_VERSION"));
+
+/// A username and password to log into a site.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// The HTTP client shared by every extractor and download.
+///
+/// Cloning a session is cheap and the clones share connections and cookies.
+#[derive(Clone)]
+pub struct Session {
+    client: Client,
+    cookies: Arc<Jar>,
+    credentials: Vec<(String, Credentials)>,
+    debug_dump: Option<PathBuf>,
+    rate_limiter: Arc<RateLimiter>,
+    read_timeout: Option<Duration>,
+    reporter: Option<Reporter>,
+    retry: RetryPolicy,
+    site_headers: Vec<(String, HeaderName, HeaderValue)>,
+    site_retry: Vec<(String, RetryPolicy)>,
+}
+
+impl Session {
+    /// Creates a session with the default settings.
+    pub fn new() -> Result<Self> {
+        SessionBuilder::new().build()
+    }
+
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder::new()
+    }
+
+    /// The cookie jar shared by all the requests of this session.
+    pub fn cookies(&self) -> &Arc<Jar> {
+        &self.cookies
+    }
+
+    /// Adds cookies to the jar, expired ones are left out.
+    pub fn add_cookies(&self, cookies: &[Cookie]) -> Result<()> {
+        add_cookies(&self.cookies, cookies)
+    }
+
+    /// The credentials given for the site the url belongs to.
+    pub fn credentials(&self, url: &Url) -> Option<&Credentials> {
+        self.credentials
+            .iter()
+            .find(|(domain, _)| host_matches(url, domain))
+            .map(|(_, credentials)| credentials)
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn head<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.head(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Sends the request, turning error status codes into the matching [`Error`].
+    ///
+    /// Failures that may be temporary are retried according to the [`RetryPolicy`] of the site.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let request = request.build()?;
+
+        match request.try_clone() {
+            Some(_) => {
+                let url = request.url().clone();
+
+                // try_clone succeeded once so it does for every attempt
+                self.retry(&url, || self.execute(request.try_clone().unwrap()))
+                    .await
+            }
+            None => self.execute(request).await,
+        }
+    }
+
+    /// Sends the request only once, without retrying.
+    pub(crate) async fn send_once(&self, request: RequestBuilder) -> Result<Response> {
+        self.execute(request.build()?).await
+    }
+
+    async fn execute(&self, mut request: Request) -> Result<Response> {
+        for (domain, name, value) in self.site_headers.iter() {
+            if host_matches(request.url(), domain) && !request.headers().contains_key(name) {
+                request.headers_mut().insert(name.clone(), value.clone());
+            }
+        }
+
+        self.rate_limiter.acquire(request.url()).await;
+
+        check_status(self.read(self.client.execute(request)).await?)
+    }
+
+    /// Waits for a read, like the response or the next chunk of its body,
+    /// failing if nothing is received within the read timeout.
+    pub(crate) async fn read<T, F>(&self, read: F) -> Result<T>
+    where
+        F: Future<Output = reqwest::Result<T>>,
+    {
+        let result = match self.read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, read)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "read timed out"))?,
+            None => read.await,
+        };
+
+        Ok(result?)
+    }
+
+    /// Runs `f` until it succeeds, it fails with an error that is not retryable
+    /// or the [`RetryPolicy`] of the site `url` belongs to gives up.
+    /// Sites asking to wait with `Retry-After` are waited for, up to the `max_retry_after` of the policy.
+    pub async fn retry<T, F, Fut>(&self, url: &Url, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let policy = self.retry_policy(url);
+        let mut attempt = 1;
+
+        loop {
+            match f().await {
+                Err(error) if error.is_retryable() && attempt < policy.max_attempts => {
+                    attempt += 1;
+
+                    let delay = match &error {
+                        Error::RateLimited {
+                            retry_after: Some(retry_after),
+                            ..
+                        } => (*retry_after).min(policy.max_retry_after),
+                        _ => policy.delay(attempt),
+                    };
+
+                    self.report(Progress::Retry {
+                        url: url.clone(),
+                        attempt,
+                        delay,
+                        error: error.to_string(),
+                    });
+
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The retry policy used for requests to the url.
+    pub fn retry_policy(&self, url: &Url) -> &RetryPolicy {
+        self.site_retry
+            .iter()
+            .find(|(domain, _)| host_matches(url, domain))
+            .map_or(&self.retry, |(_, policy)| policy)
+    }
+
+    /// Saves a raw response in the debug dump directory, if there is one.
+    ///
+    /// The file goes in a directory named after the extractor and its name is
+    /// prefixed with the time, so that dumps from different runs never collide.
+    /// Failing to write a dump never fails the extraction.
+    pub async fn dump(&self, extractor: &str, name: &str, contents: impl AsRef<[u8]>) {
+        let directory = match &self.debug_dump {
+            Some(directory) => directory.join(extractor),
+            None => return,
+        };
+
+        let file_name = format!("{}-{}", Utc::now().format("%Y%m%dT%H%M%S%.3f"), name);
+
+        if fs::create_dir_all(&directory).await.is_ok() {
+            fs::write(directory.join(file_name), contents).await.ok();
+        }
+    }
+
+    /// Sends the event to the reporter, if there is one.
+    pub fn report(&self, progress: Progress) {
+        if let Some(reporter) = &self.reporter {
+            reporter(progress);
+        }
+    }
+
+    /// Fetches the url and returns the body as text.
+    pub async fn get_text<U: IntoUrl>(&self, url: U) -> Result<String> {
+        Ok(self.send(self.get(url)).await?.text().await?)
+    }
+}
+
+/// A builder to configure a [`Session`].
+pub struct SessionBuilder {
+    connect_timeout: Option<Duration>,
+    cookies: Vec<(String, String, String)>,
+    cookie_sources: Vec<CookieSource>,
+    credentials: Vec<(String, Credentials)>,
+    debug_dump: Option<PathBuf>,
+    headers: Vec<(String, String)>,
+    proxy: Option<String>,
+    rate_limits: Vec<(String, RateLimit)>,
+    read_timeout: Option<Duration>,
+    reporter: Option<Reporter>,
+    retry: RetryPolicy,
+    site_headers: Vec<(String, String, String)>,
+    site_retry: Vec<(String, RetryPolicy)>,
+    timeout: Option<Duration>,
+    user_agent: String,
+}
+
+impl SessionBuilder {
+    pub fn new() -> Self {
+        SessionBuilder {
+            connect_timeout: None,
+            cookies: vec![],
+            cookie_sources: vec![],
+            credentials: vec![],
+            debug_dump: None,
+            headers: vec![],
+            proxy: None,
+            rate_limits: vec![],
+            read_timeout: None,
+            reporter: None,
+            retry: RetryPolicy::default(),
+            site_headers: vec![],
+            site_retry: vec![],
+            timeout: None,
+            user_agent: USER_AGENT.to_string(),
+        }
+    }
+
+    /// Timeout for establishing the connection.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Adds a cookie sent to `domain` and its subdomains.
+    pub fn cookie(mut self, domain: &str, name: &str, value: &str) -> Self {
+        self.cookies
+            .push((domain.to_string(), name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Imports the cookies of a file or a browser when the session is built.
+    pub fn import_cookies(mut self, source: CookieSource) -> Self {
+        self.cookie_sources.push(source);
+        self
+    }
+
+    /// The credentials extractors log into `domain` and its subdomains with.
+    pub fn credentials(mut self, domain: &str, credentials: Credentials) -> Self {
+        self.credentials.push((domain.to_string(), credentials));
+        self
+    }
+
+    /// Saves the raw responses parsed by the extractors in `directory`,
+    /// useful to report a site changing its layout.
+    pub fn debug_dump<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.debug_dump = Some(directory.into());
+        self
+    }
+
+    /// Adds a header sent with every request.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Routes every request through a proxy.
+    ///
+    /// Both HTTP(S) and SOCKS5 proxies are supported, e.g. `socks5://127.0.0.1:9050`.
+    pub fn proxy(mut self, proxy: &str) -> Self {
+        self.proxy = Some(proxy.to_string());
+        self
+    }
+
+    /// Limits the requests sent to each host of `domain` and its subdomains,
+    /// replacing the default limit of the site if it has one.
+    pub fn rate_limit(mut self, domain: &str, limit: RateLimit) -> Self {
+        self.rate_limits.push((domain.to_string(), limit));
+        self
+    }
+
+    /// Timeout for each read, waiting for the response or for the next part of its body.
+    ///
+    /// Unlike [`SessionBuilder::timeout`], it doesn't limit how long a big download takes.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Receives the [`Progress`] of everything done with the session.
+    pub fn reporter<F: Fn(Progress) + Send + Sync + 'static>(mut self, reporter: F) -> Self {
+        self.reporter = Some(Arc::new(reporter));
+        self
+    }
+
+    /// The retry policy used for every site without its own.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Adds a header sent with the requests to `domain` and its subdomains,
+    /// replacing the default header of the site if it has one.
+    pub fn site_header(mut self, domain: &str, name: &str, value: &str) -> Self {
+        self.site_headers
+            .push((domain.to_string(), name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The retry policy used for `domain` and its subdomains.
+    pub fn site_retry(mut self, domain: &str, policy: RetryPolicy) -> Self {
+        self.site_retry.push((domain.to_string(), policy));
+        self
+    }
+
+    /// Timeout for a whole request, reading the response included.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    pub fn build(mut self) -> Result<Session> {
+        let cookies = Arc::new(Jar::default());
+        for (domain, name, value) in self.cookies.iter() {
+            let url: Url = format!("https://{}/", domain).parse()?;
+            cookies.add_cookie_str(&format!("{}={}; Domain={}", name, value, domain), &url);
+        }
+        for source in self.cookie_sources.iter() {
+            add_cookies(&cookies, &source.load()?)?;
+        }
+
+        // The limits set by the user come first so they win over the defaults
+        self.rate_limits.extend(ratelimit::defaults());
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.headers.iter() {
+            let (name, value) = parse_header(name, value)?;
+            headers.append(name, value);
+        }
+
+        // Like rate limits, the headers set by the user win over the defaults
+        let site_headers = self
+            .site_headers
+            .iter()
+            .map(|(domain, name, value)| (domain.as_str(), name.as_str(), value.as_str()))
+            .chain(pixiv::HEADERS.iter().copied())
+            .map(|(domain, name, value)| {
+                let (name, value) = parse_header(name, value)?;
+                Ok((domain.to_string(), name, value))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut builder = Client::builder()
+            .cookie_provider(cookies.clone())
+            .default_headers(headers)
+            .user_agent(&self.user_agent);
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        Ok(Session {
+            client: builder.build()?,
+            cookies,
+            credentials: self.credentials,
+            debug_dump: self.debug_dump,
+            rate_limiter: Arc::new(RateLimiter::new(self.rate_limits)),
+            read_timeout: self.read_timeout,
+            reporter: self.reporter,
+            retry: self.retry,
+            site_headers,
+            site_retry: self.site_retry,
+        })
+    }
+}
+
+fn parse_header(name: &str, value: &str) -> Result<(HeaderName, HeaderValue)> {
+    let invalid = || Error::InvalidHeader(name.to_string());
+
+    Ok((
+        name.parse().map_err(|_| invalid())?,
+        value.parse().map_err(|_| invalid())?,
+    ))
+}
+
+fn add_cookies(jar: &Jar, cookies: &[Cookie]) -> Result<()> {
+    for cookie in cookies.iter().filter(|cookie| !cookie.is_expired()) {
+        let (header, url) = cookie.to_set_cookie();
+        jar.add_cookie_str(&header, &url.parse()?);
+    }
+
+    Ok(())
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        SessionBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn retry_after_capped() {
+        let session = Session::builder()
+            .retry(RetryPolicy {
+                max_retry_after: Duration::from_millis(10),
+                ..RetryPolicy::default()
+            })
+            .build()
+            .unwrap();
+        let url: Url = "https://nhentai.net/".parse().unwrap();
+        let attempts = AtomicU32::new(0);
+
+        let result = session
+            .retry(&url, || async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(Error::RateLimited {
+                        host: "nhentai.net".to_string(),
+                        retry_after: Some(Duration::from_secs(3600)),
+                    }),
+                    _ => Ok(()),
+                }
+            })
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn config_rate_limit_beats_default() {
+        let session = crate::Config::parse(
+            r#"
+            [sites."e-hentai.org"]
+            rate_limit = { per_second = 1.0, burst = 2 }
+            "#,
+        )
+        .unwrap()
+        .session_builder()
+        .build()
+        .unwrap();
+
+        let url = |url: &str| url.parse::<Url>().unwrap();
+        assert_eq!(
+            session
+                .rate_limiter
+                .limit(&url("https://e-hentai.org/g/1/a/")),
+            Some(RateLimit {
+                per_second: 1.0,
+                burst: 2
+            })
+        );
+        // The other sites keep their defaults
+        assert_eq!(
+            session
+                .rate_limiter
+                .limit(&url("https://exhentai.org/g/1/a/")),
+            Some(RateLimit {
+                per_second: 4.0,
+                burst: 4
+            })
+        );
+    }
+}
@@ -1,22 +1,53 @@
-use anyhow::Result;
+use async_trait::async_trait;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
 use url::Url;
 
-pub async fn main(url: &str) -> Result<()> {
-    let resp = reqwest::get(url).await?.text().await?;
-    fs::write("temp/zoo.html", &resp).await?;
+use crate::error::{Error, Result};
+use crate::extractor::{host_matches, Extractor, Media};
+use crate::session::Session;
+
+pub struct YouTube;
+
+#[async_trait]
+impl Extractor for YouTube {
+    fn name(&self) -> &'static str {
+        "youtube"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        (host_matches(url, "youtube.com") && url.path() == "/watch")
+            || host_matches(url, "youtu.be")
+    }
+
+    async fn extract(&self, session: &Session, url: &Url) -> Result<Media> {
+        Ok(Media::Video(get_video(session, url.as_str()).await?))
+    }
+}
+
+pub async fn get_video(session: &Session, url: &str) -> Result<YtInitialPlayerResponse> {
+    let resp = session.get_text(url).await?;
+    session.dump("youtube", "page.html", &resp).await;
 
     let re = Regex::new(r"ytInitialPlayerResponse\s*=\s*(\{.+?\});")?;
 
-    let yt = re.captures(&resp).unwrap().get(1).unwrap().as_str();
-    fs::write("temp/yt.json", yt).await?;
+    let yt = re
+        .captures(&resp)
+        .and_then(|c| c.get(1))
+        .ok_or(Error::LayoutChanged {
+            site: "youtube",
+            what: "ytInitialPlayerResponse",
+        })?
+        .as_str();
+    session.dump("youtube", "player_response.json", yt).await;
 
     let yt: YtInitialPlayerResponse = serde_json::from_str(yt)?;
-    fs::write("temp/yt.pretty.json", serde_json::to_string_pretty(&yt)?).await?;
 
-    for format in yt.streaming_data.adaptive_formats {
+    Ok(yt)
+}
+
+pub fn print_formats(yt: &YtInitialPlayerResponse) {
+    for format in yt.streaming_data.adaptive_formats.iter() {
         match format {
             AdaptiveFormats::Video { url, .. } => {
                 println!("Video url: {}", &url)
@@ -36,10 +67,6 @@ pub async fn main(url: &str) -> Result<()> {
             }
         }
     }
-
-    // dbg!(&yt);
-
-    Ok(())
 }
 
 #[derive(Debug, Deserialize, Serialize)]
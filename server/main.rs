@@ -1,3 +1,62 @@
-fn main() {
-    println!("Hello, world!");
+//! Downloads the urls it reads on its standard input, one per line, with the
+//! settings of the same config file as the CLI.
+//!
+//! It has no API of its own yet, whatever feeds it urls decides what to download.
+
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+use yuyu_core::{Config, Progress, Registry, Result};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let config = match (args.next().as_deref(), args.next()) {
+        (Some("--config"), Some(path)) => Config::load(path)?,
+        _ => Config::load_default()?,
+    };
+    for key in config.ignored.iter() {
+        eprintln!("Unknown config key: {}", key);
+    }
+
+    let session = config
+        .session_builder()
+        .reporter(|progress| match progress {
+            Progress::Comic { title, pages } => {
+                eprintln!("Downloading {} ({} pages)", title, pages)
+            }
+            Progress::Skipped { title, path } => {
+                eprintln!("{} was already downloaded to {}", title, path.display())
+            }
+            Progress::Page { .. } => (),
+            Progress::Retry {
+                url,
+                attempt,
+                delay,
+                error,
+            } => eprintln!(
+                "{}, retrying {} in {:.1}s (attempt {})",
+                error,
+                url,
+                delay.as_secs_f32(),
+                attempt
+            ),
+        })
+        .build()?;
+    let options = config.download_options();
+    let registry = Registry::with_options(&config.extractor_options());
+
+    let mut lines = BufReader::new(io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        let url = line.trim();
+        if url.is_empty() {
+            continue;
+        }
+
+        // One failed url doesn't stop the others
+        match yuyu_core::start(&session, &registry, url, &options).await {
+            Ok(()) => println!("{}", url),
+            Err(error) => eprintln!("{}: {}", url, error),
+        }
+    }
+
+    Ok(())
 }